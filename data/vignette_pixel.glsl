@@ -0,0 +1,13 @@
+uniform sampler2D al_tex;
+varying vec4 varying_color;
+varying vec2 varying_texcoord;
+uniform vec2 bitmap_dims;
+uniform float damage;
+
+void main()
+{
+	vec4 color = varying_color * texture2D(al_tex, varying_texcoord);
+	float radius = length(varying_texcoord - vec2(0.5, 0.5));
+	float f = damage * smoothstep(0.3, 0.7, radius);
+	gl_FragColor = vec4(mix(color.rgb, vec3(0.6, 0., 0.), f), color.a);
+}
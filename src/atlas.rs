@@ -28,10 +28,35 @@ impl AtlasBitmap
 	}
 }
 
+/// Takes a `w` by `h` area from the first free rectangle it fits in, returning
+/// its top-left corner. What's left of the rectangle stays free.
+fn take_free(free: &mut Vec<(Point2<f32>, Point2<f32>)>, w: f32, h: f32) -> Option<Point2<f32>>
+{
+	let i = free
+		.iter()
+		.position(|(start, end)| end.x - start.x >= w && end.y - start.y >= h)?;
+	let (start, end) = free.swap_remove(i);
+	// Split off the strips to the right of and below the taken area.
+	if end.x - start.x > w
+	{
+		free.push((
+			Point2::new(start.x + w, start.y),
+			Point2::new(end.x, start.y + h),
+		));
+	}
+	if end.y - start.y > h
+	{
+		free.push((Point2::new(start.x, start.y + h), end));
+	}
+	Some(start)
+}
+
 pub struct Page
 {
 	pub bitmap: Bitmap,
 	packer: rect_packer::Packer,
+	// Space given back by removed entries, which the packer can't reclaim.
+	free: Vec<(Point2<f32>, Point2<f32>)>,
 }
 
 impl Page
@@ -53,35 +78,47 @@ impl Page
 		core.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
 
 		Ok(Page {
-			bitmap: bitmap,
+			bitmap,
 			packer: rect_packer::Packer::new(config),
+			free: vec![],
 		})
 	}
 
 	fn insert<B: BitmapLike>(&mut self, core: &Core, bitmap: &B, page: usize)
 		-> Option<AtlasBitmap>
 	{
-		if let Some(placement) = self
-			.packer
-			.pack(bitmap.get_width(), bitmap.get_height(), false)
+		let (w, h) = (bitmap.get_width(), bitmap.get_height());
+		let start = if let Some(start) = take_free(&mut self.free, w as f32, h as f32)
 		{
-			core.set_target_bitmap(Some(&self.bitmap));
-			core.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::Zero);
-			core.draw_bitmap(bitmap, placement.x as f32, placement.y as f32, Flag::zero());
-			core.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
-			Some(AtlasBitmap {
-				start: Point2::new(placement.x as f32, placement.y as f32),
-				end: Point2::new(
-					(placement.x + placement.width) as f32,
-					(placement.y + placement.height) as f32,
-				),
-				page: page,
-			})
+			start
 		}
 		else
 		{
-			None
-		}
+			let placement = self.packer.pack(w, h, false)?;
+			Point2::new(placement.x as f32, placement.y as f32)
+		};
+		core.set_target_bitmap(Some(&self.bitmap));
+		core.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::Zero);
+		core.draw_bitmap(bitmap, start.x, start.y, Flag::zero());
+		core.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
+		Some(AtlasBitmap {
+			start,
+			end: Point2::new(start.x + w as f32, start.y + h as f32),
+			page,
+		})
+	}
+
+	fn replace<B: BitmapLike>(&mut self, core: &Core, atlas_bitmap: &AtlasBitmap, bitmap: &B)
+	{
+		core.set_target_bitmap(Some(&self.bitmap));
+		core.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::Zero);
+		core.draw_bitmap(
+			bitmap,
+			atlas_bitmap.start.x,
+			atlas_bitmap.start.y,
+			Flag::zero(),
+		);
+		core.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
 	}
 }
 
@@ -99,7 +136,7 @@ impl Atlas
 		Self {
 			pages: vec![],
 			bitmaps: HashMap::new(),
-			page_size: page_size,
+			page_size,
 		}
 	}
 
@@ -117,7 +154,7 @@ impl Atlas
 		let id = self.pages.len() - 1;
 		if let Some(atlas_bitmap) = self.pages.last_mut().unwrap().insert(core, bitmap, id)
 		{
-			return Ok(atlas_bitmap);
+			Ok(atlas_bitmap)
 		}
 		else
 		{
@@ -130,6 +167,30 @@ impl Atlas
 		}
 	}
 
+	/// Redraws an existing entry in place. Returns false if the new bitmap has
+	/// a different size, in which case nothing is changed.
+	pub fn replace<B: BitmapLike>(
+		&mut self, core: &Core, atlas_bitmap: &AtlasBitmap, bitmap: &B,
+	) -> bool
+	{
+		if atlas_bitmap.width() as i32 != bitmap.get_width()
+			|| atlas_bitmap.height() as i32 != bitmap.get_height()
+		{
+			return false;
+		}
+		self.pages[atlas_bitmap.page].replace(core, atlas_bitmap, bitmap);
+		true
+	}
+
+	/// Frees the space used by an entry, so later insertions can reuse it.
+	pub fn remove(&mut self, atlas_bitmap: &AtlasBitmap)
+	{
+		if let Some(page) = self.pages.get_mut(atlas_bitmap.page)
+		{
+			page.free.push((atlas_bitmap.start, atlas_bitmap.end));
+		}
+	}
+
 	pub fn insert_from_file(&mut self, core: &Core, filename: &str) -> Result<AtlasBitmap>
 	{
 		let old_flags = core.get_new_bitmap_flags();
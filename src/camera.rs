@@ -0,0 +1,146 @@
+use crate::utils;
+
+use nalgebra::{Point3, SVector, Vector2, Vector3};
+
+pub const MIN_ZOOM: f32 = 0.5;
+pub const MAX_ZOOM: f32 = 2.;
+// Screen pixels per second.
+const FREE_LOOK_SPEED: f32 = 400.;
+// Teleports cut straight to the target instead of sweeping across the map.
+const SNAP_DISTANCE: f32 = 10.;
+
+/// Moves `pos` towards `goal` like a critically damped spring, reaching it in
+/// roughly `smooth_time` seconds without overshooting.
+fn smooth_damp<const D: usize>(
+	pos: SVector<f32, D>, goal: SVector<f32, D>, vel: &mut SVector<f32, D>, smooth_time: f32,
+	dt: f32,
+) -> SVector<f32, D>
+{
+	if smooth_time <= 0.
+	{
+		*vel = SVector::zeros();
+		return goal;
+	}
+	let omega = 2. / smooth_time;
+	let x = omega * dt;
+	// Approximates exp(-x), but stays stable for large time steps.
+	let decay = 1. / (1. + x + 0.48 * x * x + 0.235 * x * x * x);
+	let change = pos - goal;
+	let temp = (*vel + omega * change) * dt;
+	*vel = (*vel - omega * temp) * decay;
+	goal + (change + temp) * decay
+}
+
+#[derive(Clone, Debug)]
+pub struct Camera
+{
+	pub pos: Point3<f32>,
+	vel: Vector3<f32>,
+	pub zoom: f32,
+	target_zoom: f32,
+	// Screen space offset, moved around by free-look.
+	pub look_offset: Vector2<f32>,
+	look_vel: Vector2<f32>,
+	free_look: bool,
+	// Quarter turns of the view, 0 to 3.
+	pub rotation: i32,
+	// The state at the previous tick, see `interpolate`.
+	prev_pos: Point3<f32>,
+	prev_zoom: f32,
+	prev_look_offset: Vector2<f32>,
+}
+
+impl Camera
+{
+	pub fn new(pos: Point3<f32>) -> Self
+	{
+		Self {
+			pos,
+			vel: Vector3::zeros(),
+			zoom: 1.,
+			target_zoom: 1.,
+			look_offset: Vector2::zeros(),
+			look_vel: Vector2::zeros(),
+			free_look: false,
+			rotation: 0,
+			prev_pos: pos,
+			prev_zoom: 1.,
+			prev_look_offset: Vector2::zeros(),
+		}
+	}
+
+	/// Remembers the current state, call this at the start of each tick.
+	pub fn snapshot(&mut self)
+	{
+		self.prev_pos = self.pos;
+		self.prev_zoom = self.zoom;
+		self.prev_look_offset = self.look_offset;
+	}
+
+	/// The camera `alpha` of the way from the previous tick to this one.
+	pub fn interpolate(&self, alpha: f32) -> Camera
+	{
+		let mut camera = self.clone();
+		camera.pos = self.prev_pos + (self.pos - self.prev_pos) * alpha;
+		camera.zoom = self.prev_zoom + (self.zoom - self.prev_zoom) * alpha;
+		camera.look_offset =
+			self.prev_look_offset + (self.look_offset - self.prev_look_offset) * alpha;
+		camera
+	}
+
+	pub fn rotate(&mut self, quarter_turns: i32)
+	{
+		self.rotation = (self.rotation + quarter_turns).rem_euclid(4);
+		// The offset was in the old screen space.
+		self.look_offset = Vector2::zeros();
+		self.look_vel = Vector2::zeros();
+		self.prev_look_offset = Vector2::zeros();
+	}
+
+	pub fn zoom_by(&mut self, factor: f32)
+	{
+		self.target_zoom = utils::clamp(self.target_zoom * factor, MIN_ZOOM, MAX_ZOOM);
+	}
+
+	/// Pans the view by `dir` (in screen space, -1 to 1 on each axis) for this
+	/// tick. The view drifts back to the target once this stops being called.
+	pub fn free_look(&mut self, dir: Vector2<f32>, dt: f32)
+	{
+		self.free_look = true;
+		self.look_offset += dir * FREE_LOOK_SPEED * dt / self.zoom;
+		self.look_vel = Vector2::zeros();
+	}
+
+	/// Follows `target`, leading it by `look_ahead` seconds of its velocity.
+	pub fn update(
+		&mut self, target: Point3<f32>, target_vel: Vector3<f32>, smooth_time: f32,
+		look_ahead: f32, dt: f32,
+	)
+	{
+		let mut goal = target;
+		goal.x += target_vel.x * look_ahead;
+		goal.y += target_vel.y * look_ahead;
+		if (goal - self.pos).norm() > SNAP_DISTANCE
+		{
+			self.pos = goal;
+			self.vel = Vector3::zeros();
+		}
+		self.pos.coords = smooth_damp(self.pos.coords, goal.coords, &mut self.vel, smooth_time, dt);
+
+		if !self.free_look
+		{
+			self.look_offset = smooth_damp(
+				self.look_offset,
+				Vector2::zeros(),
+				&mut self.look_vel,
+				smooth_time,
+				dt,
+			);
+		}
+		self.free_look = false;
+
+		// Zoom in equal ratios, so zooming in and out feel the same.
+		let f = 1. - (-dt * 10.).exp();
+		self.zoom *= (self.target_zoom / self.zoom).powf(f);
+	}
+}
@@ -1,5 +1,5 @@
 use crate::sprite;
-use na::{Point2, Point3, Vector3};
+use na::{Point2, Point3, Vector2, Vector3};
 use nalgebra as na;
 
 #[derive(Debug, Copy, Clone)]
@@ -9,6 +9,14 @@ pub struct Position
 	pub dir: f32,
 }
 
+// Where a moving entity was at the previous tick, to draw it in between.
+#[derive(Debug, Copy, Clone)]
+pub struct PrevPosition
+{
+	pub pos: Point3<f32>,
+	pub dir: f32,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Velocity
 {
@@ -48,6 +56,19 @@ pub enum DrawableKind
 	},
 }
 
+impl DrawableKind
+{
+	pub fn get_sprite(&self) -> &str
+	{
+		match self
+		{
+			DrawableKind::Oriented { sprite } => sprite,
+			DrawableKind::Animated { sprite, .. } => sprite,
+			DrawableKind::Fixed { sprite, .. } => sprite,
+		}
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct Drawable
 {
@@ -60,22 +81,14 @@ pub struct TimeToDie
 	pub time_to_die: f64,
 }
 
-#[derive(Debug, Clone)]
-pub enum ParticleKind
-{
-	Stationary,
-	Fire,
-}
-
 #[derive(Debug, Clone)]
 pub struct ParticleSpawner
 {
 	pub offset: Vector3<f32>,
-	pub kind: ParticleKind,
-	pub spawn_delay: f64,
+	// The emitter config file.
+	pub emitter: String,
 	pub time_to_spawn: f64,
-	pub duration: f64,
-	pub sprite: String,
+	pub started: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -84,6 +97,13 @@ pub struct ParticleSpawners
 	pub spawners: Vec<ParticleSpawner>,
 }
 
+#[derive(Debug, Clone)]
+pub struct Particle
+{
+	pub emitter: String,
+	pub creation_time: f64,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum ExplosionKind
 {
@@ -114,6 +134,38 @@ pub struct WaterCollector
 	pub water_amount: i32,
 }
 
+#[derive(Debug, Clone)]
+pub struct Fuel
+{
+	pub fuel: f32,
+	pub max_fuel: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DroppedBy
+{
+	pub pilot: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Wingman
+{
+	pub refilling: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Health
+{
+	pub health: f32,
+	pub max_health: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Airstrip
+{
+	pub half_extents: Vector2<f32>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Mushroom
 {
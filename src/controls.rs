@@ -1,6 +1,7 @@
+use crate::utils;
 use serde_derive::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt;
 
 #[derive(PartialEq, Eq, Hash, Serialize, Deserialize, Copy, Clone, Debug, PartialOrd, Ord)]
@@ -13,11 +14,52 @@ pub enum Action
 	DropWater,
 	Restart,
 	Minimap,
+	Autopilot,
+	ZoomIn,
+	ZoomOut,
+	FreeLook,
+	RotateLeft,
+	RotateRight,
+	Pause,
+	MenuConfirm,
+	MenuBack,
+	MenuUp,
+	MenuDown,
+	MenuLeft,
+	MenuRight,
 }
 
 impl Action
 {
-	pub fn to_str(&self) -> &'static str
+	/// Menu actions are only used in menus, so they can share inputs with the
+	/// in-game actions.
+	pub fn is_menu(&self) -> bool
+	{
+		matches!(
+			self,
+			Action::MenuConfirm
+				| Action::MenuBack
+				| Action::MenuUp
+				| Action::MenuDown
+				| Action::MenuLeft
+				| Action::MenuRight
+		)
+	}
+
+	/// Camera actions only change this machine's view of the game.
+	pub fn is_camera(&self) -> bool
+	{
+		matches!(
+			self,
+			Action::ZoomIn
+				| Action::ZoomOut
+				| Action::FreeLook
+				| Action::RotateLeft
+				| Action::RotateRight
+		)
+	}
+
+	pub fn to_str(self) -> &'static str
 	{
 		match self
 		{
@@ -28,10 +70,40 @@ impl Action
 			Action::DropWater => "DROP WATER",
 			Action::Restart => "RESTART",
 			Action::Minimap => "MINIMAP",
+			Action::Autopilot => "AUTOPILOT",
+			Action::ZoomIn => "ZOOM IN",
+			Action::ZoomOut => "ZOOM OUT",
+			Action::FreeLook => "FREE LOOK",
+			Action::RotateLeft => "ROTATE LEFT",
+			Action::RotateRight => "ROTATE RIGHT",
+			Action::Pause => "PAUSE",
+			Action::MenuConfirm => "MENU CONFIRM",
+			Action::MenuBack => "MENU BACK",
+			Action::MenuUp => "MENU UP",
+			Action::MenuDown => "MENU DOWN",
+			Action::MenuLeft => "MENU LEFT",
+			Action::MenuRight => "MENU RIGHT",
 		}
 	}
 }
 
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ActionEventKind
+{
+	Pressed,
+	Released,
+	// Sent every tick the action is down, including the one it was pressed.
+	Held,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ActionEvent
+{
+	pub action: Action,
+	pub kind: ActionEventKind,
+	pub strength: f32,
+}
+
 #[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, PartialOrd, Ord)]
 pub enum Input
 {
@@ -43,11 +115,18 @@ pub enum Input
 	MouseXNeg,
 	MouseYNeg,
 	MouseZNeg,
+	// Joystick, button.
+	JoystickButton(i32, i32),
+	// Joystick, stick, axis.
+	JoystickAxisPos(i32, i32, i32),
+	JoystickAxisNeg(i32, i32, i32),
+	JoystickHatPos(i32, i32, i32),
+	JoystickHatNeg(i32, i32, i32),
 }
 
 impl Input
 {
-	pub fn to_str(&self) -> &'static str
+	pub fn to_str(self) -> String
 	{
 		match self
 		{
@@ -172,7 +251,8 @@ impl Input
 				allegro::KeyCode::ScrollLock => "ScrollLock",
 				allegro::KeyCode::NumLock => "NumLock",
 				allegro::KeyCode::CapsLock => "CapsLock",
-			},
+			}
+			.into(),
 			Input::MouseButton(b) => match b
 			{
 				0 => "Mouse0",
@@ -195,13 +275,19 @@ impl Input
 				17 => "Mouse17",
 				18 => "Mouse18",
 				b => panic!("button too high: {b}"),
-			},
-			Input::MouseXNeg => "MouseX-",
-			Input::MouseYNeg => "MouseY-",
-			Input::MouseZNeg => "MouseZ-",
-			Input::MouseXPos => "MouseX+",
-			Input::MouseYPos => "MouseY+",
-			Input::MouseZPos => "MouseZ+",
+			}
+			.into(),
+			Input::MouseXNeg => "MouseX-".into(),
+			Input::MouseYNeg => "MouseY-".into(),
+			Input::MouseZNeg => "MouseZ-".into(),
+			Input::MouseXPos => "MouseX+".into(),
+			Input::MouseYPos => "MouseY+".into(),
+			Input::MouseZPos => "MouseZ+".into(),
+			Input::JoystickButton(j, b) => format!("Joy {} Button {}", j, b),
+			Input::JoystickAxisPos(j, s, a) => format!("Joy {} Axis {}/{}+", j, s, a),
+			Input::JoystickAxisNeg(j, s, a) => format!("Joy {} Axis {}/{}-", j, s, a),
+			Input::JoystickHatPos(j, s, a) => format!("Joy {} Hat {}/{}+", j, s, a),
+			Input::JoystickHatNeg(j, s, a) => format!("Joy {} Hat {}/{}-", j, s, a),
 		}
 	}
 
@@ -374,8 +460,91 @@ impl Input
 			}
 		}
 
+		if input.is_none()
+		{
+			input = Input::joystick_from_str(s);
+		}
+
 		input
 	}
+
+	fn joystick_from_str(s: &str) -> Option<Self>
+	{
+		let s = s.strip_prefix("Joy ")?;
+		// Bindings saved before joysticks were told apart are for the first one.
+		let (joystick, s) = match s.split_once(' ')
+		{
+			Some((joystick, rest)) if joystick.parse::<i32>().is_ok() =>
+			{
+				(joystick.parse().ok()?, rest)
+			}
+			_ => (0, s),
+		};
+		if let Some(button) = s.strip_prefix("Button ")
+		{
+			return Some(Input::JoystickButton(joystick, button.parse().ok()?));
+		}
+		let (rest, hat) = if let Some(rest) = s.strip_prefix("Axis ")
+		{
+			(rest, false)
+		}
+		else if let Some(rest) = s.strip_prefix("Hat ")
+		{
+			(rest, true)
+		}
+		else
+		{
+			return None;
+		};
+		let (rest, positive) = if let Some(rest) = rest.strip_suffix('+')
+		{
+			(rest, true)
+		}
+		else
+		{
+			(rest.strip_suffix('-')?, false)
+		};
+		let (stick, axis) = rest.split_once('/')?;
+		let stick = stick.parse().ok()?;
+		let axis = axis.parse().ok()?;
+		Some(match (hat, positive)
+		{
+			(false, true) => Input::JoystickAxisPos(joystick, stick, axis),
+			(false, false) => Input::JoystickAxisNeg(joystick, stick, axis),
+			(true, true) => Input::JoystickHatPos(joystick, stick, axis),
+			(true, false) => Input::JoystickHatNeg(joystick, stick, axis),
+		})
+	}
+
+	/// The index of a connected joystick, so several can be told apart.
+	fn joystick_index(joystick: *mut allegro_sys::ALLEGRO_JOYSTICK) -> i32
+	{
+		let num_joysticks = unsafe { allegro_sys::al_get_num_joysticks() };
+		(0..num_joysticks)
+			.find(|&i| unsafe { allegro_sys::al_get_joystick(i) } == joystick)
+			.unwrap_or(0)
+	}
+
+	fn joystick_button(joystick: *mut allegro_sys::ALLEGRO_JOYSTICK, button: i32) -> Self
+	{
+		Input::JoystickButton(Input::joystick_index(joystick), button)
+	}
+
+	fn joystick_axis(
+		joystick: *mut allegro_sys::ALLEGRO_JOYSTICK, stick: i32, axis: i32, positive: bool,
+	) -> Self
+	{
+		let flags = unsafe { allegro_sys::al_get_joystick_stick_flags(joystick, stick) };
+		let hat = flags as u32 & allegro_sys::ALLEGRO_JOYFLAG_DIGITAL != 0;
+		let index = Input::joystick_index(joystick);
+		match (hat, positive)
+		{
+			(false, true) => Input::JoystickAxisPos(index, stick, axis),
+			(false, false) => Input::JoystickAxisNeg(index, stick, axis),
+			(true, true) => Input::JoystickHatPos(index, stick, axis),
+			(true, false) => Input::JoystickHatNeg(index, stick, axis),
+		}
+	}
 }
 
 impl serde::Serialize for Input
@@ -384,7 +553,7 @@ impl serde::Serialize for Input
 	where
 		S: serde::Serializer,
 	{
-		serializer.serialize_str(self.to_str())
+		serializer.serialize_str(&self.to_str())
 	}
 }
 
@@ -402,7 +571,7 @@ impl<'de> serde::de::Visitor<'de> for InputVisitor
 	where
 		E: serde::de::Error,
 	{
-		Input::from_str(&value).ok_or(serde::de::Error::invalid_value(
+		Input::from_str(value).ok_or(serde::de::Error::invalid_value(
 			serde::de::Unexpected::Str(value),
 			&self,
 		))
@@ -455,6 +624,11 @@ impl InputState
 		max_strength
 	}
 
+	fn held(&self) -> bool
+	{
+		self.queue.last().cloned().unwrap_or(self.strength) > 0.5
+	}
+
 	fn clear(&mut self)
 	{
 		self.strength = 0.;
@@ -462,73 +636,368 @@ impl InputState
 	}
 }
 
+/// An input, optionally chorded with modifiers that must be held for it to
+/// register.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Binding
+{
+	modifiers: Vec<Input>,
+	input: Input,
+}
+
+impl Binding
+{
+	pub fn new(input: Input) -> Self
+	{
+		Self {
+			modifiers: vec![],
+			input,
+		}
+	}
+
+	pub fn with_modifiers(modifiers: Vec<Input>, input: Input) -> Self
+	{
+		Self { modifiers, input }
+	}
+
+	pub fn to_str(&self) -> String
+	{
+		let mut parts: Vec<_> = self.modifiers.iter().map(|m| m.to_str()).collect();
+		parts.push(self.input.to_str());
+		parts.join(" + ")
+	}
+
+	pub fn from_str(s: &str) -> Option<Self>
+	{
+		let mut inputs = vec![];
+		for part in s.split(" + ")
+		{
+			inputs.push(Input::from_str(part)?);
+		}
+		let input = inputs.pop()?;
+		Some(Self {
+			modifiers: inputs,
+			input,
+		})
+	}
+}
+
+impl serde::Serialize for Binding
+{
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		serializer.serialize_str(&self.to_str())
+	}
+}
+
+struct BindingVisitor;
+
+impl<'de> serde::de::Visitor<'de> for BindingVisitor
+{
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(formatter, "a Binding")
+	}
+
+	type Value = Binding;
+	fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+	where
+		E: serde::de::Error,
+	{
+		Binding::from_str(value).ok_or(serde::de::Error::invalid_value(
+			serde::de::Unexpected::Str(value),
+			&self,
+		))
+	}
+}
+
+impl<'de> serde::Deserialize<'de> for Binding
+{
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: serde::Deserializer<'de>,
+	{
+		deserializer.deserialize_str(BindingVisitor)
+	}
+}
+
+fn default_dead_zone() -> f32
+{
+	0.2
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Controls
 {
-	action_to_inputs: BTreeMap<Action, [Option<Input>; 2]>,
+	name: String,
+	bindings: BTreeMap<Action, Vec<Binding>>,
 	mouse_sensitivity: f32,
+	#[serde(default = "default_dead_zone")]
+	dead_zone: f32,
+	#[serde(default)]
+	mouse_flight: bool,
+	#[serde(default)]
+	invert_y: bool,
 }
 
 impl Controls
 {
+	fn from_bindings(name: &str, bindings: &[(Action, &[Input])]) -> Self
+	{
+		let menu_bindings: &[(Action, &[Input])] = &[
+			(
+				Action::Pause,
+				&[
+					Input::Keyboard(allegro::KeyCode::Escape),
+					Input::JoystickButton(0, 7),
+				],
+			),
+			(
+				Action::MenuConfirm,
+				&[
+					Input::Keyboard(allegro::KeyCode::Enter),
+					Input::Keyboard(allegro::KeyCode::Space),
+					Input::JoystickButton(0, 0),
+				],
+			),
+			(
+				Action::MenuBack,
+				&[
+					Input::Keyboard(allegro::KeyCode::Escape),
+					Input::JoystickButton(0, 1),
+				],
+			),
+			(
+				Action::MenuUp,
+				&[
+					Input::Keyboard(allegro::KeyCode::Up),
+					Input::JoystickAxisNeg(0, 0, 1),
+				],
+			),
+			(
+				Action::MenuDown,
+				&[
+					Input::Keyboard(allegro::KeyCode::Down),
+					Input::JoystickAxisPos(0, 0, 1),
+				],
+			),
+			(
+				Action::MenuLeft,
+				&[
+					Input::Keyboard(allegro::KeyCode::Left),
+					Input::JoystickAxisNeg(0, 0, 0),
+				],
+			),
+			(
+				Action::MenuRight,
+				&[
+					Input::Keyboard(allegro::KeyCode::Right),
+					Input::JoystickAxisPos(0, 0, 0),
+				],
+			),
+		];
+		let mut action_bindings = BTreeMap::new();
+		for &(action, inputs) in bindings.iter().chain(menu_bindings.iter())
+		{
+			action_bindings.insert(action, inputs.iter().map(|&i| Binding::new(i)).collect());
+		}
+		Self {
+			name: name.into(),
+			bindings: action_bindings,
+			mouse_sensitivity: 0.1,
+			dead_zone: default_dead_zone(),
+			mouse_flight: false,
+			invert_y: false,
+		}
+	}
+
 	pub fn new() -> Self
 	{
-		let mut action_to_inputs = BTreeMap::new();
-		action_to_inputs.insert(
-			Action::TurnLeft,
-			[Some(Input::Keyboard(allegro::KeyCode::Left)), None],
-		);
-		action_to_inputs.insert(
-			Action::TurnRight,
-			[Some(Input::Keyboard(allegro::KeyCode::Right)), None],
-		);
-		action_to_inputs.insert(
-			Action::Ascend,
-			[Some(Input::Keyboard(allegro::KeyCode::Up)), None],
-		);
-		action_to_inputs.insert(
-			Action::Descend,
-			[Some(Input::Keyboard(allegro::KeyCode::Down)), None],
-		);
-		action_to_inputs.insert(
-			Action::DropWater,
-			[Some(Input::Keyboard(allegro::KeyCode::Space)), None],
-		);
-		action_to_inputs.insert(
-			Action::Restart,
-			[Some(Input::Keyboard(allegro::KeyCode::R)), None],
-		);
-		action_to_inputs.insert(
-			Action::Minimap,
-			[Some(Input::Keyboard(allegro::KeyCode::Tab)), None],
+		Controls::from_bindings(
+			"arrows",
+			&[
+				(Action::TurnLeft, &[Input::Keyboard(allegro::KeyCode::Left)]),
+				(
+					Action::TurnRight,
+					&[Input::Keyboard(allegro::KeyCode::Right)],
+				),
+				(Action::Ascend, &[Input::Keyboard(allegro::KeyCode::Up)]),
+				(Action::Descend, &[Input::Keyboard(allegro::KeyCode::Down)]),
+				(
+					Action::DropWater,
+					&[Input::Keyboard(allegro::KeyCode::Space)],
+				),
+				(Action::Restart, &[Input::Keyboard(allegro::KeyCode::R)]),
+				(Action::Minimap, &[Input::Keyboard(allegro::KeyCode::Tab)]),
+				(Action::Autopilot, &[Input::Keyboard(allegro::KeyCode::P)]),
+				(
+					Action::ZoomIn,
+					&[Input::MouseZPos, Input::Keyboard(allegro::KeyCode::PadPlus)],
+				),
+				(
+					Action::ZoomOut,
+					&[
+						Input::MouseZNeg,
+						Input::Keyboard(allegro::KeyCode::PadMinus),
+					],
+				),
+				(
+					Action::FreeLook,
+					&[Input::Keyboard(allegro::KeyCode::RShift)],
+				),
+				(
+					Action::RotateLeft,
+					&[Input::Keyboard(allegro::KeyCode::Comma)],
+				),
+				(
+					Action::RotateRight,
+					&[Input::Keyboard(allegro::KeyCode::Fullstop)],
+				),
+			],
+		)
+	}
+
+	pub fn new_wasd() -> Self
+	{
+		Controls::from_bindings(
+			"wasd",
+			&[
+				(Action::TurnLeft, &[Input::Keyboard(allegro::KeyCode::A)]),
+				(Action::TurnRight, &[Input::Keyboard(allegro::KeyCode::D)]),
+				(Action::Ascend, &[Input::Keyboard(allegro::KeyCode::W)]),
+				(Action::Descend, &[Input::Keyboard(allegro::KeyCode::S)]),
+				(
+					Action::DropWater,
+					&[Input::Keyboard(allegro::KeyCode::LCtrl)],
+				),
+				(Action::Restart, &[]),
+				(Action::Minimap, &[Input::Keyboard(allegro::KeyCode::Q)]),
+				(Action::Autopilot, &[Input::Keyboard(allegro::KeyCode::E)]),
+				// The mouse wheel belongs to the arrows profile, so two players on
+				// one keyboard don't both zoom.
+				(Action::ZoomIn, &[Input::Keyboard(allegro::KeyCode::X)]),
+				(Action::ZoomOut, &[Input::Keyboard(allegro::KeyCode::Z)]),
+				(
+					Action::FreeLook,
+					&[Input::Keyboard(allegro::KeyCode::LShift)],
+				),
+				(Action::RotateLeft, &[Input::Keyboard(allegro::KeyCode::_1)]),
+				(
+					Action::RotateRight,
+					&[Input::Keyboard(allegro::KeyCode::_3)],
+				),
+			],
+		)
+	}
+
+	pub fn new_mouse_flight() -> Self
+	{
+		let mut controls = Controls::from_bindings(
+			"mouse flight",
+			&[
+				(Action::TurnLeft, &[Input::MouseXNeg]),
+				(Action::TurnRight, &[Input::MouseXPos]),
+				(Action::Ascend, &[Input::MouseYNeg]),
+				(Action::Descend, &[Input::MouseYPos]),
+				(Action::DropWater, &[Input::MouseButton(1)]),
+				(Action::Restart, &[Input::Keyboard(allegro::KeyCode::R)]),
+				(Action::Minimap, &[Input::Keyboard(allegro::KeyCode::Tab)]),
+				(Action::Autopilot, &[Input::MouseButton(2)]),
+				(Action::ZoomIn, &[Input::MouseZPos]),
+				(Action::ZoomOut, &[Input::MouseZNeg]),
+				(
+					Action::FreeLook,
+					&[Input::Keyboard(allegro::KeyCode::LShift)],
+				),
+				(Action::RotateLeft, &[Input::Keyboard(allegro::KeyCode::Q)]),
+				(Action::RotateRight, &[Input::Keyboard(allegro::KeyCode::E)]),
+			],
 		);
+		controls.mouse_flight = true;
+		controls
+	}
 
-		Self {
-			action_to_inputs: action_to_inputs,
-			mouse_sensitivity: 0.1,
+	pub fn default_profiles() -> Vec<Self>
+	{
+		vec![
+			Controls::new(),
+			Controls::new_wasd(),
+			Controls::new_mouse_flight(),
+		]
+	}
+
+	pub fn get_name(&self) -> &str
+	{
+		&self.name
+	}
+
+	/// Profiles saved before an action existed get its default bindings.
+	pub fn add_missing_actions(&mut self)
+	{
+		for (action, bindings) in Controls::new().bindings
+		{
+			self.bindings.entry(action).or_insert(bindings);
 		}
 	}
 
 	pub fn get_action_string(&self, action: Action) -> String
 	{
 		let mut inputs = vec![];
-		for input in self.action_to_inputs.get(&action).unwrap()
+		for binding in self.bindings.get(&action).into_iter().flatten()
 		{
-			if let Some(input) = input
-			{
-				inputs.push(input.to_str());
-			}
+			inputs.push(binding.to_str());
 		}
 		inputs.join("/")
 	}
 }
 
+/// The controls saved before named profiles existed, with at most two inputs
+/// per action.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename = "Controls")]
+pub struct LegacyControls
+{
+	action_to_inputs: BTreeMap<Action, [Option<Input>; 2]>,
+	mouse_sensitivity: f32,
+	#[serde(default = "default_dead_zone")]
+	dead_zone: f32,
+}
+
+impl LegacyControls
+{
+	/// Overrides the bindings and settings in `controls` with the legacy ones.
+	pub fn apply(&self, controls: &mut Controls)
+	{
+		for (&action, inputs) in &self.action_to_inputs
+		{
+			controls.bindings.insert(
+				action,
+				inputs.iter().flatten().map(|&i| Binding::new(i)).collect(),
+			);
+		}
+		controls.mouse_sensitivity = self.mouse_sensitivity;
+		controls.dead_zone = self.dead_zone;
+	}
+}
+
 #[derive(Clone, Debug)]
 pub struct ControlsHandler
 {
 	controls: Controls,
-	input_to_action: BTreeMap<Input, Action>,
 	input_state: HashMap<Input, InputState>,
+	// Inputs held down while a new binding is being captured.
+	captured: Vec<Input>,
+	// Virtual cursor offset for mouse flight, within the unit circle.
+	mouse_offset: utils::Vec2D,
+	action_states: BTreeMap<Action, f32>,
+	action_events: Vec<ActionEvent>,
+	event_actions: Vec<Action>,
+	// Actions that flip on each press rather than being held.
+	toggle_actions: BTreeSet<Action>,
+	toggled: BTreeSet<Action>,
+	raw_states: BTreeMap<Action, f32>,
 }
 
 impl ControlsHandler
@@ -536,9 +1005,16 @@ impl ControlsHandler
 	pub fn new(controls: Controls) -> Self
 	{
 		let mut ret = Self {
-			controls: controls,
-			input_to_action: BTreeMap::new(),
+			controls,
 			input_state: HashMap::new(),
+			captured: vec![],
+			mouse_offset: utils::Vec2D::zeros(),
+			action_states: BTreeMap::new(),
+			action_events: vec![],
+			event_actions: vec![],
+			toggle_actions: BTreeSet::new(),
+			toggled: BTreeSet::new(),
+			raw_states: BTreeMap::new(),
 		};
 		ret.update_derived();
 		ret
@@ -547,28 +1023,23 @@ impl ControlsHandler
 	pub fn update_derived(&mut self)
 	{
 		self.input_state.clear();
-		for inputs in self.controls.action_to_inputs.values()
+		for bindings in self.controls.bindings.values()
 		{
-			for input in &inputs[..]
+			for binding in bindings
 			{
-				if let Some(input) = input
+				self.input_state.insert(binding.input, InputState::new());
+				for &modifier in &binding.modifiers
 				{
-					self.input_state.insert(*input, InputState::new());
+					self.input_state.insert(modifier, InputState::new());
 				}
 			}
 		}
-		self.input_to_action.clear();
-		for (action, [input1, input2]) in &self.controls.action_to_inputs
-		{
-			if let Some(input1) = input1
-			{
-				self.input_to_action.insert(*input1, *action);
-			}
-			if let Some(input2) = input2
-			{
-				self.input_to_action.insert(*input2, *action);
-			}
-		}
+	}
+
+	pub fn set_toggle_actions(&mut self, actions: &[Action])
+	{
+		self.toggle_actions = actions.iter().cloned().collect();
+		self.toggled.clear();
 	}
 
 	pub fn get_controls(&self) -> &Controls
@@ -586,18 +1057,94 @@ impl ControlsHandler
 		self.controls.mouse_sensitivity = mouse_sensitivity;
 	}
 
-	pub fn get_actions_to_inputs(&self) -> impl Iterator<Item = (&Action, &[Option<Input>; 2])>
+	pub fn get_dead_zone(&self) -> f32
 	{
-		self.controls.action_to_inputs.iter()
+		self.controls.dead_zone
 	}
 
-	pub fn get_inputs(&self, action: Action) -> Option<&[Option<Input>; 2]>
+	pub fn set_dead_zone(&mut self, dead_zone: f32)
 	{
-		self.controls.action_to_inputs.get(&action)
+		self.controls.dead_zone = dead_zone;
+	}
+
+	pub fn get_mouse_flight(&self) -> bool
+	{
+		self.controls.mouse_flight
+	}
+
+	pub fn set_mouse_flight(&mut self, mouse_flight: bool)
+	{
+		self.controls.mouse_flight = mouse_flight;
+		self.mouse_offset = utils::Vec2D::zeros();
+		self.update_derived();
 	}
 
-	pub fn decode_event(&mut self, event: &allegro::Event) -> Vec<(f32, Action)>
+	pub fn get_invert_y(&self) -> bool
 	{
+		self.controls.invert_y
+	}
+
+	pub fn set_invert_y(&mut self, invert_y: bool)
+	{
+		self.controls.invert_y = invert_y;
+	}
+
+	/// The virtual cursor offset, if mouse flight is on.
+	pub fn get_mouse_flight_offset(&self) -> Option<utils::Vec2D>
+	{
+		if self.controls.mouse_flight
+		{
+			Some(self.mouse_offset)
+		}
+		else
+		{
+			None
+		}
+	}
+
+	fn steer_mouse_flight(&mut self, dx: i32, dy: i32)
+	{
+		// At the default sensitivity, 200 pixels gives the full rate.
+		let scale = 0.05 * self.controls.mouse_sensitivity;
+		self.mouse_offset += utils::Vec2D::new(dx as f32, dy as f32) * scale;
+		let len = self.mouse_offset.norm();
+		if len > 1.
+		{
+			self.mouse_offset /= len;
+		}
+		let offset = self.mouse_offset;
+		// Unlike the impulses, these persist until the cursor moves again.
+		for (input, strength) in [
+			(Input::MouseXPos, offset.x),
+			(Input::MouseXNeg, -offset.x),
+			(Input::MouseYPos, offset.y),
+			(Input::MouseYNeg, -offset.y),
+		]
+		{
+			if let Some(state) = self.input_state.get_mut(&input)
+			{
+				state.push(utils::max(0., strength));
+			}
+		}
+	}
+
+	pub fn get_bindings(&self) -> impl Iterator<Item = (&Action, &Vec<Binding>)>
+	{
+		self.controls.bindings.iter()
+	}
+
+	pub fn get_action_bindings(&self, action: Action) -> &[Binding]
+	{
+		self.controls
+			.bindings
+			.get(&action)
+			.map(|b| &b[..])
+			.unwrap_or(&[])
+	}
+
+	pub fn decode_event(&mut self, event: &allegro::Event)
+	{
+		self.event_actions = self.get_pressed_actions(event);
 		match event
 		{
 			allegro::Event::KeyDown { keycode, .. } =>
@@ -634,36 +1181,44 @@ impl ControlsHandler
 			}
 			allegro::Event::MouseAxes { dx, dy, dz, .. } =>
 			{
-				if *dx < 0
+				let dy = if self.controls.invert_y { -*dy } else { *dy };
+				if self.controls.mouse_flight
 				{
-					if let Some(state) = self.input_state.get_mut(&Input::MouseXNeg)
-					{
-						state.push(self.controls.mouse_sensitivity * -*dx as f32);
-						state.push(0.);
-					}
+					self.steer_mouse_flight(*dx, dy);
 				}
-				else if *dx > 0
+				else
 				{
-					if let Some(state) = self.input_state.get_mut(&Input::MouseXPos)
+					if *dx < 0
 					{
-						state.push(self.controls.mouse_sensitivity * *dx as f32);
-						state.push(0.);
+						if let Some(state) = self.input_state.get_mut(&Input::MouseXNeg)
+						{
+							state.push(self.controls.mouse_sensitivity * -*dx as f32);
+							state.push(0.);
+						}
 					}
-				}
-				if *dy < 0
-				{
-					if let Some(state) = self.input_state.get_mut(&Input::MouseYNeg)
+					else if *dx > 0
 					{
-						state.push(self.controls.mouse_sensitivity * -*dy as f32);
-						state.push(0.);
+						if let Some(state) = self.input_state.get_mut(&Input::MouseXPos)
+						{
+							state.push(self.controls.mouse_sensitivity * *dx as f32);
+							state.push(0.);
+						}
 					}
-				}
-				else if *dy > 0
-				{
-					if let Some(state) = self.input_state.get_mut(&Input::MouseYPos)
+					if dy < 0
 					{
-						state.push(self.controls.mouse_sensitivity * *dy as f32);
-						state.push(0.);
+						if let Some(state) = self.input_state.get_mut(&Input::MouseYNeg)
+						{
+							state.push(self.controls.mouse_sensitivity * -dy as f32);
+							state.push(0.);
+						}
+					}
+					else if dy > 0
+					{
+						if let Some(state) = self.input_state.get_mut(&Input::MouseYPos)
+						{
+							state.push(self.controls.mouse_sensitivity * dy as f32);
+							state.push(0.);
+						}
 					}
 				}
 				if *dz < 0
@@ -683,72 +1238,312 @@ impl ControlsHandler
 					}
 				}
 			}
+			allegro::Event::JoystickButtonDown { id, button, .. } =>
+			{
+				let input = Input::joystick_button(*id, *button);
+				if let Some(state) = self.input_state.get_mut(&input)
+				{
+					state.push(1.);
+				}
+			}
+			allegro::Event::JoystickButtonUp { id, button, .. } =>
+			{
+				let input = Input::joystick_button(*id, *button);
+				if let Some(state) = self.input_state.get_mut(&input)
+				{
+					state.push(0.);
+				}
+			}
+			allegro::Event::JoystickAxes {
+				id,
+				stick,
+				axis,
+				pos,
+				..
+			} =>
+			{
+				let pos_input = Input::joystick_axis(*id, *stick, *axis, true);
+				let neg_input = Input::joystick_axis(*id, *stick, *axis, false);
+				// Rescale so the strength starts from 0 at the edge of the dead zone.
+				let dead_zone = utils::clamp(self.controls.dead_zone, 0., 0.99);
+				let strength = utils::max(0., (pos.abs() - dead_zone) / (1. - dead_zone));
+				let (pos_strength, neg_strength) = if *pos > 0.
+				{
+					(strength, 0.)
+				}
+				else
+				{
+					(0., strength)
+				};
+				if let Some(state) = self.input_state.get_mut(&pos_input)
+				{
+					state.push(pos_strength);
+				}
+				if let Some(state) = self.input_state.get_mut(&neg_input)
+				{
+					state.push(neg_strength);
+				}
+			}
 			_ => (),
 		}
-		vec![]
 	}
 
-	pub fn get_action_state(&mut self, action: Action) -> f32
+	/// The actions pressed by the most recently decoded event. The UI uses
+	/// these, since it reacts to events rather than ticks.
+	pub fn get_event_actions(&self) -> &[Action]
 	{
-		let mut ret = 0.;
-		for inputs in &self.controls.action_to_inputs.get(&action)
+		&self.event_actions
+	}
+
+	fn get_pressed_actions(&self, event: &allegro::Event) -> Vec<Action>
+	{
+		let pressed = match event
+		{
+			allegro::Event::KeyDown { keycode, .. } => Some(Input::Keyboard(*keycode)),
+			allegro::Event::MouseButtonDown { button, .. } =>
+			{
+				Some(Input::MouseButton(*button as i32))
+			}
+			allegro::Event::JoystickButtonDown { id, button, .. } =>
+			{
+				Some(Input::joystick_button(*id, *button))
+			}
+			allegro::Event::JoystickAxes {
+				id,
+				stick,
+				axis,
+				pos,
+				..
+			} =>
+			{
+				let input = Input::joystick_axis(*id, *stick, *axis, *pos > 0.);
+				let was_held = self
+					.input_state
+					.get(&input)
+					.map(|s| s.held())
+					.unwrap_or(false);
+				if pos.abs() > 0.5 && !was_held
+				{
+					Some(input)
+				}
+				else
+				{
+					None
+				}
+			}
+			// Each wheel click counts as a press.
+			allegro::Event::MouseAxes { dz, .. } => match dz.cmp(&0)
+			{
+				Ordering::Less => Some(Input::MouseZNeg),
+				Ordering::Greater => Some(Input::MouseZPos),
+				Ordering::Equal => None,
+			},
+			_ => None,
+		};
+		let mut actions = vec![];
+		if let Some(pressed) = pressed
 		{
-			for input in &inputs[..]
+			for (&action, bindings) in &self.controls.bindings
 			{
-				if let Some(input) = input
+				if bindings.iter().any(|b| {
+					b.input == pressed && b.modifiers.iter().all(|m| self.input_state[m].held())
+				})
 				{
-					ret += self.input_state.get_mut(input).unwrap().get();
+					actions.push(action);
 				}
 			}
 		}
-		return ret;
+		actions
 	}
 
-	pub fn clear_action_state(&mut self, action: Action)
+	/// Samples every action and generates this tick's events. Call this once
+	/// per logic tick, before querying any action state.
+	pub fn update(&mut self) -> &[ActionEvent]
 	{
-		for inputs in &self.controls.action_to_inputs.get(&action)
+		self.action_events.clear();
+		let actions: Vec<_> = self.controls.bindings.keys().cloned().collect();
+		for action in actions
 		{
-			for input in &inputs[..]
+			let mut strength = self.sample_action(action);
+			let old_raw = self.raw_states.insert(action, strength).unwrap_or(0.);
+			if self.toggle_actions.contains(&action)
 			{
-				if let Some(input) = input
+				if strength > 0.5 && old_raw <= 0.5 && !self.toggled.remove(&action)
+				{
+					self.toggled.insert(action);
+				}
+				strength = if self.toggled.contains(&action)
 				{
-					self.input_state.get_mut(input).unwrap().clear();
+					1.
 				}
+				else
+				{
+					0.
+				};
+			}
+			let old_strength = self.action_states.insert(action, strength).unwrap_or(0.);
+			let down = strength > 0.5;
+			let was_down = old_strength > 0.5;
+			let mut push = |kind| {
+				self.action_events.push(ActionEvent {
+					action,
+					kind,
+					strength,
+				})
+			};
+			if down && !was_down
+			{
+				push(ActionEventKind::Pressed);
+			}
+			else if !down && was_down
+			{
+				push(ActionEventKind::Released);
+			}
+			if down
+			{
+				push(ActionEventKind::Held);
+			}
+		}
+		&self.action_events
+	}
+
+	pub fn get_action_events(&self) -> &[ActionEvent]
+	{
+		&self.action_events
+	}
+
+	fn has_event(&self, action: Action, kind: ActionEventKind) -> bool
+	{
+		self.action_events
+			.iter()
+			.any(|e| e.action == action && e.kind == kind)
+	}
+
+	pub fn is_pressed(&self, action: Action) -> bool
+	{
+		self.has_event(action, ActionEventKind::Pressed)
+	}
+
+	pub fn is_released(&self, action: Action) -> bool
+	{
+		self.has_event(action, ActionEventKind::Released)
+	}
+
+	pub fn is_held(&self, action: Action) -> bool
+	{
+		self.has_event(action, ActionEventKind::Held)
+	}
+
+	/// The strength of the action as of the last `update`.
+	pub fn get_action_state(&self, action: Action) -> f32
+	{
+		self.action_states.get(&action).cloned().unwrap_or(0.)
+	}
+
+	fn sample_action(&mut self, action: Action) -> f32
+	{
+		let mut ret = 0.;
+		for binding in self.controls.bindings.get(&action).into_iter().flatten()
+		{
+			let chorded = binding.modifiers.iter().all(|m| self.input_state[m].held());
+			// Always drain the queue, so stale presses don't fire once the
+			// modifiers are held.
+			let strength = self.input_state.get_mut(&binding.input).unwrap().get();
+			if chorded
+			{
+				ret += strength;
 			}
 		}
+		ret
+	}
+
+	pub fn clear_action_state(&mut self, action: Action)
+	{
+		self.action_states.remove(&action);
+		self.action_events.retain(|e| e.action != action);
+		for binding in self.controls.bindings.get(&action).into_iter().flatten()
+		{
+			self.input_state.get_mut(&binding.input).unwrap().clear();
+		}
 	}
 
 	pub fn clear_action(&mut self, action: Action, index: usize)
 	{
-		self.controls.action_to_inputs.get_mut(&action).unwrap()[index] = None;
+		if let Some(bindings) = self.controls.bindings.get_mut(&action)
+		{
+			if index < bindings.len()
+			{
+				bindings.remove(index);
+			}
+		}
 		self.update_derived();
 	}
 
+	fn set_binding(&mut self, action: Action, index: usize, binding: Binding)
+	{
+		// Each binding belongs to at most one action in each context.
+		for (&other_action, bindings) in self.controls.bindings.iter_mut()
+		{
+			if other_action != action && other_action.is_menu() == action.is_menu()
+			{
+				bindings.retain(|b| *b != binding);
+			}
+		}
+		let bindings = self.controls.bindings.entry(action).or_insert(vec![]);
+		if bindings.contains(&binding)
+		{
+			return;
+		}
+		if index < bindings.len()
+		{
+			bindings[index] = binding;
+		}
+		else
+		{
+			bindings.push(binding);
+		}
+	}
+
+	/// Captures a new binding for `action` at `index` (which can be one past
+	/// the end, to add a binding). Buttons are bound once released, and any
+	/// other buttons held at that point become its modifiers. Returns `Some(true)` once
+	/// a binding was made, `Some(false)` if capturing was cancelled and `None`
+	/// if more input is needed.
 	pub fn change_action(
 		&mut self, action: Action, index: usize, event: &allegro::Event,
 	) -> Option<bool>
 	{
-		let mut handled = false;
-		let new_input = match event
+		let (pressed, released, moved) = match event
 		{
 			allegro::Event::KeyDown { keycode, .. } =>
 			{
-				handled = true;
-				match *keycode
+				if *keycode == allegro::KeyCode::Escape
 				{
-					allegro::KeyCode::Escape => None,
-					keycode => Some(Input::Keyboard(keycode)),
+					self.captured.clear();
+					return Some(false);
 				}
+				(Some(Input::Keyboard(*keycode)), None, None)
+			}
+			allegro::Event::KeyUp { keycode, .. } => (None, Some(Input::Keyboard(*keycode)), None),
+			allegro::Event::MouseButtonDown { button, .. } =>
+			{
+				(Some(Input::MouseButton(*button as i32)), None, None)
 			}
 			allegro::Event::MouseButtonUp { button, .. } =>
 			{
-				handled = true;
-				Some(Input::MouseButton(*button as i32))
+				(None, Some(Input::MouseButton(*button as i32)), None)
+			}
+			allegro::Event::JoystickButtonDown { id, button, .. } =>
+			{
+				(Some(Input::joystick_button(*id, *button)), None, None)
+			}
+			allegro::Event::JoystickButtonUp { id, button, .. } =>
+			{
+				(None, Some(Input::joystick_button(*id, *button)), None)
 			}
 			allegro::Event::MouseAxes { dx, dy, dz, .. } =>
 			{
-				handled = true;
-				match (dx.cmp(&0), dy.cmp(&0), dz.cmp(&0))
+				let moved = match (dx.cmp(&0), dy.cmp(&0), dz.cmp(&0))
 				{
 					(Ordering::Less, _, _) => Some(Input::MouseXNeg),
 					(Ordering::Greater, _, _) => Some(Input::MouseXPos),
@@ -757,41 +1552,90 @@ impl ControlsHandler
 					(_, _, Ordering::Less) => Some(Input::MouseZNeg),
 					(_, _, Ordering::Greater) => Some(Input::MouseZPos),
 					_ => None,
+				};
+				(None, None, moved)
+			}
+			allegro::Event::JoystickAxes {
+				id,
+				stick,
+				axis,
+				pos,
+				..
+			} =>
+			{
+				// Ignore small wobbles, only a deliberate push binds the axis.
+				let moved = if pos.abs() > 0.5
+				{
+					Some(Input::joystick_axis(*id, *stick, *axis, *pos > 0.))
 				}
+				else
+				{
+					None
+				};
+				(None, None, moved)
 			}
-			_ => None,
+			_ => (None, None, None),
 		};
-		if let Some(new_input) = new_input
+
+		if let Some(pressed) = pressed
 		{
-			if self.input_to_action.contains_key(&new_input)
+			if !self.captured.contains(&pressed)
 			{
-				let old_input = self.controls.action_to_inputs[&action][index];
-				let other_action = self.input_to_action[&new_input];
-				let other_inputs = self
-					.controls
-					.action_to_inputs
-					.get_mut(&other_action)
-					.unwrap();
+				self.captured.push(pressed);
+			}
+			return None;
+		}
 
-				if other_inputs[0] == Some(new_input)
-				{
-					other_inputs[0] = old_input;
-				}
-				else if other_inputs[1] == Some(new_input)
-				{
-					other_inputs[1] = old_input;
-				}
+		let binding = if let Some(released) = released
+		{
+			// Releasing something pressed before capturing started.
+			if !self.captured.contains(&released)
+			{
+				return None;
 			}
-			self.controls.action_to_inputs.get_mut(&action).unwrap()[index] = Some(new_input);
+			let modifiers = self
+				.captured
+				.iter()
+				.cloned()
+				.filter(|&i| i != released)
+				.collect();
+			Binding::with_modifiers(modifiers, released)
 		}
-		if handled
+		else if let Some(moved) = moved
 		{
-			self.update_derived();
-			Some(new_input.is_some())
+			// Axes can't be held down, so they bind immediately.
+			Binding::with_modifiers(self.captured.clone(), moved)
 		}
 		else
 		{
-			None
-		}
+			return None;
+		};
+
+		self.captured.clear();
+		self.set_binding(action, index, binding);
+		self.update_derived();
+		Some(true)
+	}
+}
+
+#[test]
+fn test_joystick_input_strings()
+{
+	for input in [
+		Input::JoystickButton(1, 3),
+		Input::JoystickAxisPos(0, 1, 2),
+		Input::JoystickHatNeg(2, 0, 1),
+	]
+	{
+		assert_eq!(Input::from_str(&input.to_str()), Some(input));
 	}
+	// Saved before joysticks were told apart.
+	assert_eq!(
+		Input::from_str("Joy Button 3"),
+		Some(Input::JoystickButton(0, 3))
+	);
+	assert_eq!(
+		Input::from_str("Joy Axis 0/1-"),
+		Some(Input::JoystickAxisNeg(0, 0, 1))
+	);
 }
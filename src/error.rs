@@ -15,8 +15,8 @@ impl Error
 	pub fn new(message: String, inner: Option<Box<dyn error::Error + 'static>>) -> Self
 	{
 		Self {
-			message: message,
-			inner: inner,
+			message,
+			inner,
 			backtrace: Backtrace::capture(),
 		}
 	}
@@ -93,7 +93,7 @@ impl error::Error for Error
 {
 	fn source(&self) -> Option<&(dyn error::Error + 'static)>
 	{
-		self.inner.as_ref().map(|e| &**e)
+		self.inner.as_deref()
 	}
 }
 
@@ -1,5 +1,5 @@
 use crate::error::Result;
-use crate::{atlas, controls, sfx, sprite, utils};
+use crate::{atlas, controls, net, particles, sfx, sprite, utils};
 use allegro::*;
 use allegro_font::*;
 use allegro_image::*;
@@ -11,6 +11,7 @@ use std::collections::HashMap;
 use std::{fmt, path};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Options
 {
 	pub fullscreen: bool,
@@ -18,6 +19,11 @@ pub struct Options
 	pub height: i32,
 	pub play_music: bool,
 	pub vsync_method: i32,
+	// Size of the buffer the game is drawn into, before scaling to the display.
+	pub buffer_width: i32,
+	pub buffer_height: i32,
+	pub integer_scaling: bool,
+	pub letterbox_color: [f32; 3],
 	pub sfx_volume: f32,
 	pub music_volume: f32,
 	pub map_size: i32,
@@ -25,9 +31,39 @@ pub struct Options
 	pub fire_spread_probability: f32,
 	pub obelisk_factor: f32,
 	pub water_factor: f32,
+	pub num_wingmen: i32,
+	pub night_mission: bool,
+	// Seconds for a full day and night, 0 stops the clock.
+	pub day_length: f32,
+	pub num_players: i32,
+	pub host_address: String,
+	pub net_port: i32,
 	pub seed: Option<u64>,
 
-	pub controls: controls::Controls,
+	pub game_speed: f32,
+	pub auto_level: bool,
+	pub auto_drop_water: bool,
+	// How high above the terrain the autopilot flies.
+	pub autopilot_clearance: f32,
+	// How many seconds ahead the collision alert and autopilot look.
+	pub collision_look_ahead: f32,
+	// Roughly how many seconds the camera takes to catch up with the plane.
+	pub camera_smoothing: f32,
+	// How many seconds ahead of the plane the camera centers on.
+	pub camera_look_ahead: f32,
+	// Reload assets from `data/` when they change on disk.
+	pub dev_mode: bool,
+	// Actions that switch on and off with each press, instead of being held.
+	pub toggle_actions: Vec<controls::Action>,
+
+	pub control_profiles: Vec<controls::Controls>,
+	// The profile name each local player uses.
+	pub player_profiles: Vec<String>,
+	// Written by older versions, and moved into `control_profiles` on load.
+	#[serde(skip_serializing)]
+	pub controls: Option<controls::LegacyControls>,
+	#[serde(skip_serializing)]
+	pub controls2: Option<controls::LegacyControls>,
 }
 
 impl Default for Options
@@ -40,6 +76,10 @@ impl Default for Options
 			height: 728,
 			play_music: true,
 			vsync_method: 2,
+			buffer_width: 800,
+			buffer_height: 600,
+			integer_scaling: true,
+			letterbox_color: [0., 0., 0.],
 			sfx_volume: 1.,
 			music_volume: 1.,
 			map_size: 4,
@@ -47,8 +87,26 @@ impl Default for Options
 			fire_spread_probability: 0.5,
 			obelisk_factor: 1.,
 			water_factor: 0.2,
+			num_wingmen: 0,
+			night_mission: false,
+			day_length: 720.,
+			num_players: 1,
+			host_address: "127.0.0.1".into(),
+			net_port: 7777,
 			seed: None,
-			controls: controls::Controls::new(),
+			game_speed: 1.,
+			auto_level: false,
+			auto_drop_water: false,
+			autopilot_clearance: 4.,
+			collision_look_ahead: 1.,
+			camera_smoothing: 0.2,
+			camera_look_ahead: 0.5,
+			dev_mode: false,
+			toggle_actions: vec![],
+			control_profiles: controls::Controls::default_profiles(),
+			player_profiles: vec!["arrows".into(), "wasd".into()],
+			controls: None,
+			controls2: None,
 		}
 	}
 }
@@ -72,10 +130,18 @@ pub struct GameState
 	pub font: FontAddon,
 	pub ttf: TtfAddon,
 	pub tick: i64,
+	pub game_time: f64,
+	// Scales how fast game time passes, for slow motion.
+	pub time_scale: f32,
+	// How far the frame being drawn is from the previous logic tick to the
+	// last one, 0 to 1.
+	pub interpolation: f32,
 	pub paused: bool,
 	pub hide_mouse: bool,
 
 	pub swirl_amount: f32,
+	// Values for the named uniforms of the post-processing shaders.
+	pub shader_uniforms: HashMap<String, f32>,
 
 	pub sfx: sfx::Sfx,
 	pub atlas: atlas::Atlas,
@@ -83,11 +149,39 @@ pub struct GameState
 	pub number_font: Font,
 	pub options: Options,
 	pub draw_scale: f32,
+	pub buffer_width: f32,
+	pub buffer_height: f32,
 	pub display_width: f32,
 	pub display_height: f32,
 	bitmaps: HashMap<String, Bitmap>,
 	sprites: HashMap<String, sprite::Sprite>,
+	emitters: HashMap<String, particles::Emitter>,
 	pub controls: controls::ControlsHandler,
+	pub controls2: controls::ControlsHandler,
+	pub net: Option<net::Session>,
+}
+
+fn player_profile(options: &Options, player: usize) -> controls::Controls
+{
+	options
+		.player_profiles
+		.get(player)
+		.and_then(|name| {
+			options
+				.control_profiles
+				.iter()
+				.find(|p| p.get_name() == name)
+		})
+		.or(options.control_profiles.first())
+		.cloned()
+		.unwrap_or_else(controls::Controls::new)
+}
+
+fn player_controls(options: &Options, player: usize) -> controls::ControlsHandler
+{
+	let mut controls = controls::ControlsHandler::new(player_profile(options, player));
+	controls.set_toggle_actions(&options.toggle_actions);
+	controls
 }
 
 pub fn load_options(core: &Core) -> Result<Options>
@@ -103,7 +197,21 @@ pub fn load_options(core: &Core) -> Result<Options>
 	path_buf.push("options.cfg");
 	if path_buf.exists()
 	{
-		utils::load_config(path_buf.to_str().unwrap())
+		let file = path_buf.to_str().unwrap();
+		// A broken options file shouldn't keep the game from starting.
+		match utils::load_config(file)
+		{
+			Ok(mut options) =>
+			{
+				migrate_options(&mut options);
+				Ok(options)
+			}
+			Err(e) =>
+			{
+				println!("Couldn't load '{}', using the default options: {}", file, e);
+				Ok(Default::default())
+			}
+		}
 	}
 	else
 	{
@@ -111,6 +219,42 @@ pub fn load_options(core: &Core) -> Result<Options>
 	}
 }
 
+/// Moves the controls saved by older versions into the profiles that replaced
+/// them.
+fn migrate_options(options: &mut Options)
+{
+	for (legacy, name, default) in [
+		(
+			options.controls.take(),
+			"arrows",
+			controls::Controls::new as fn() -> _,
+		),
+		(
+			options.controls2.take(),
+			"wasd",
+			controls::Controls::new_wasd,
+		),
+	]
+	{
+		if let Some(legacy) = legacy
+		{
+			match options
+				.control_profiles
+				.iter_mut()
+				.find(|p| p.get_name() == name)
+			{
+				Some(profile) => legacy.apply(profile),
+				None =>
+				{
+					let mut profile = default();
+					legacy.apply(&mut profile);
+					options.control_profiles.push(profile);
+				}
+			}
+		}
+	}
+}
+
 pub fn save_options(core: &Core, options: &Options) -> Result<()>
 {
 	let mut path_buf = path::PathBuf::new();
@@ -123,7 +267,7 @@ pub fn save_options(core: &Core, options: &Options) -> Result<()>
 	}
 	std::fs::create_dir_all(&path_buf).map_err(|_| "Couldn't create directory".to_string())?;
 	path_buf.push("options.cfg");
-	utils::save_config(path_buf.to_str().unwrap(), &options)
+	utils::save_config(path_buf.to_str().unwrap(), options)
 }
 
 impl GameState
@@ -134,7 +278,15 @@ impl GameState
 		core.set_app_name("WeatherLesson");
 		core.set_org_name("SiegeLord");
 
-		let options = load_options(&core)?;
+		let mut options = load_options(&core)?;
+		if options.control_profiles.is_empty()
+		{
+			options.control_profiles = controls::Controls::default_profiles();
+		}
+		for profile in &mut options.control_profiles
+		{
+			profile.add_missing_actions();
+		}
 		let prim = PrimitivesAddon::init(&core)?;
 		let image = ImageAddon::init(&core)?;
 		let font = FontAddon::init(&core)?;
@@ -143,6 +295,11 @@ impl GameState
 			.map_err(|_| "Couldn't install keyboard".to_string())?;
 		core.install_mouse()
 			.map_err(|_| "Couldn't install mouse".to_string())?;
+		// Joysticks are optional, the game is playable without them.
+		if core.install_joystick().is_err()
+		{
+			println!("Couldn't install joystick, joysticks won't work.");
+		}
 
 		let sfx = sfx::Sfx::new(options.sfx_volume, options.music_volume, &core)?;
 
@@ -153,41 +310,136 @@ impl GameState
 			.load_ttf_font("data/MHTIROGLA.ttf", -32, TtfFlags::zero())
 			.map_err(|_| "Couldn't load 'data/advanced_pixel_lcd-7.ttf'".to_string())?;
 
-		let controls = controls::ControlsHandler::new(options.controls.clone());
+		let controls = player_controls(&options, 0);
+		let controls2 = player_controls(&options, 1);
 		Ok(GameState {
-			options: options,
-			core: core,
-			prim: prim,
-			image: image,
+			options,
+			core,
+			prim,
+			image,
 			tick: 0,
+			game_time: 0.,
+			time_scale: 1.,
+			interpolation: 1.,
 			bitmaps: HashMap::new(),
 			sprites: HashMap::new(),
-			font: font,
-			ttf: ttf,
-			sfx: sfx,
+			emitters: HashMap::new(),
+			font,
+			ttf,
+			sfx,
 			paused: false,
 			atlas: atlas::Atlas::new(2048),
-			ui_font: ui_font,
-			number_font: number_font,
+			ui_font,
+			number_font,
 			draw_scale: 1.,
+			buffer_width: 800.,
+			buffer_height: 600.,
 			display_width: 0.,
 			display_height: 0.,
 			swirl_amount: 0.,
+			shader_uniforms: HashMap::new(),
 			hide_mouse: false,
-			controls: controls,
+			controls,
+			controls2,
+			net: None,
 		})
 	}
 
+	pub fn update_draw_scale(&mut self)
+	{
+		self.draw_scale = utils::min(
+			self.display_width / self.buffer_width,
+			self.display_height / self.buffer_height,
+		);
+		if self.options.integer_scaling
+		{
+			self.draw_scale = utils::max(1., self.draw_scale.floor());
+		}
+	}
+
 	pub fn transform_mouse(&self, x: f32, y: f32) -> (f32, f32)
 	{
-		let bw = 800.;
-		let bh = 600.;
+		let bw = self.buffer_width;
+		let bh = self.buffer_height;
 
 		let x = (x - self.display_width / 2.) / self.draw_scale + bw / 2.;
 		let y = (y - self.display_height / 2.) / self.draw_scale + bh / 2.;
 		(x, y)
 	}
 
+	pub fn get_player_controls(&self, player: usize) -> &controls::ControlsHandler
+	{
+		if player == 0
+		{
+			&self.controls
+		}
+		else
+		{
+			&self.controls2
+		}
+	}
+
+	pub fn get_player_controls_mut(&mut self, player: usize) -> &mut controls::ControlsHandler
+	{
+		if player == 0
+		{
+			&mut self.controls
+		}
+		else
+		{
+			&mut self.controls2
+		}
+	}
+
+	pub fn get_player_profile(&self, player: usize) -> usize
+	{
+		let name = self.get_player_controls(player).get_controls().get_name();
+		self.options
+			.control_profiles
+			.iter()
+			.position(|p| p.get_name() == name)
+			.unwrap_or(0)
+	}
+
+	pub fn set_player_profile(&mut self, player: usize, profile: usize)
+	{
+		let name = self.options.control_profiles[profile]
+			.get_name()
+			.to_string();
+		while self.options.player_profiles.len() <= player
+		{
+			self.options.player_profiles.push(name.clone());
+		}
+		self.options.player_profiles[player] = name;
+		*self.get_player_controls_mut(player) = player_controls(&self.options, player);
+	}
+
+	pub fn save_player_controls(&mut self, player: usize)
+	{
+		let controls = self.get_player_controls(player).get_controls().clone();
+		let profile = self.get_player_profile(player);
+		self.options.control_profiles[profile] = controls;
+		// The other player might be sharing this profile.
+		let other = 1 - player;
+		if self.get_player_profile(other) == profile
+		{
+			*self.get_player_controls_mut(other) = player_controls(&self.options, other);
+		}
+	}
+
+	pub fn set_toggle_action(&mut self, action: controls::Action, toggle: bool)
+	{
+		self.options.toggle_actions.retain(|&a| a != action);
+		if toggle
+		{
+			self.options.toggle_actions.push(action);
+		}
+		self.controls
+			.set_toggle_actions(&self.options.toggle_actions);
+		self.controls2
+			.set_toggle_actions(&self.options.toggle_actions);
+	}
+
 	pub fn cache_bitmap<'l>(&'l mut self, name: &str) -> Result<&'l Bitmap>
 	{
 		Ok(match self.bitmaps.entry(name.to_string())
@@ -206,6 +458,94 @@ impl GameState
 		})
 	}
 
+	/// Loads a particle emitter, along with its sprite and trail.
+	pub fn cache_emitter<'l>(&'l mut self, name: &str) -> Result<&'l particles::Emitter>
+	{
+		if !self.emitters.contains_key(name)
+		{
+			let emitter = particles::Emitter::load(name)?;
+			self.emitters.insert(name.to_string(), emitter.clone());
+			if let Some(sprite) = &emitter.sprite
+			{
+				self.cache_sprite(sprite)?;
+			}
+			for other in emitter.trail.iter().chain(emitter.smoke.iter())
+			{
+				self.cache_emitter(other)?;
+			}
+		}
+		Ok(&self.emitters[name])
+	}
+
+	/// Reloads the cached bitmaps, sprites, emitters and samples that use any
+	/// of `files`. Assets that fail to reload are reported and keep their old
+	/// version.
+	pub fn reload_files(&mut self, files: &[String])
+	{
+		for file in files
+		{
+			if self.bitmaps.contains_key(file)
+			{
+				match utils::load_bitmap(&self.core, file)
+				{
+					Ok(bitmap) =>
+					{
+						self.bitmaps.insert(file.clone(), bitmap);
+					}
+					Err(e) => println!("{}", e),
+				}
+			}
+			if let Err(e) = self.sfx.reload_sample(file)
+			{
+				println!("{}", e);
+			}
+		}
+		for (name, sprite) in &mut self.sprites
+		{
+			if files.contains(name) || files.iter().any(|f| f == sprite.get_bitmap_file())
+			{
+				if let Err(e) = sprite.reload(name, &self.core, &mut self.atlas)
+				{
+					println!("{}", e.context(format!("Couldn't reload '{}'", name)));
+				}
+			}
+		}
+		let mut reloaded = vec![];
+		for (name, emitter) in &mut self.emitters
+		{
+			if files.contains(name)
+			{
+				match particles::Emitter::load(name)
+				{
+					Ok(new_emitter) =>
+					{
+						*emitter = new_emitter;
+						reloaded.push(emitter.clone());
+					}
+					Err(e) => println!("{}", e.context(format!("Couldn't reload '{}'", name))),
+				}
+			}
+		}
+		// The new sprites, trails and smoke may not have been loaded yet.
+		for emitter in reloaded
+		{
+			if let Some(sprite) = &emitter.sprite
+			{
+				if let Err(e) = self.cache_sprite(sprite)
+				{
+					println!("{}", e);
+				}
+			}
+			for other in emitter.trail.iter().chain(emitter.smoke.iter())
+			{
+				if let Err(e) = self.cache_emitter(other)
+				{
+					println!("{}", e);
+				}
+			}
+		}
+	}
+
 	pub fn get_bitmap<'l>(&'l self, name: &str) -> Option<&'l Bitmap>
 	{
 		self.bitmaps.get(name)
@@ -216,8 +556,54 @@ impl GameState
 		self.sprites.get(name)
 	}
 
+	pub fn get_emitter<'l>(&'l self, name: &str) -> Option<&'l particles::Emitter>
+	{
+		self.emitters.get(name)
+	}
+
 	pub fn time(&self) -> f64
 	{
-		self.tick as f64 * utils::DT as f64
+		self.game_time
+	}
+
+	/// The game time that passes each tick.
+	pub fn dt(&self) -> f32
+	{
+		utils::DT * self.time_scale
 	}
 }
+
+#[test]
+fn test_load_legacy_options()
+{
+	let file = std::env::temp_dir().join("legacy_options.cfg");
+	std::fs::write(
+		&file,
+		r#"
+		fullscreen = true
+		width = 1024
+		height = 728
+		toggle_actions = [DropWater]
+		controls = Controls
+		{
+			action_to_inputs = [[Ascend, [Down, ""]], [Descend, [Up, ""]]]
+			mouse_sensitivity = 0.1
+		}
+		"#,
+	)
+	.unwrap();
+	let mut options: Options = utils::load_config(file.to_str().unwrap()).unwrap();
+	migrate_options(&mut options);
+	assert!(options.controls.is_none());
+	let arrows = options
+		.control_profiles
+		.iter()
+		.find(|p| p.get_name() == "arrows")
+		.unwrap();
+	assert_eq!(arrows.get_action_string(controls::Action::Ascend), "Down");
+	assert_eq!(arrows.get_action_string(controls::Action::TurnLeft), "Left");
+	assert_eq!(options.toggle_actions, [controls::Action::DropWater]);
+
+	let options: Options = utils::load_config("options.cfg").unwrap();
+	assert!(options.controls.is_none());
+}
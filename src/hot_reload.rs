@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::SystemTime;
+
+/// Polls a directory tree for files that have been created or modified.
+pub struct FileWatcher
+{
+	root: String,
+	mtimes: HashMap<String, SystemTime>,
+	next_check: f64,
+}
+
+fn scan(dir: &str, files: &mut Vec<(String, SystemTime)>)
+{
+	let entries = match fs::read_dir(dir)
+	{
+		Ok(entries) => entries,
+		Err(_) => return,
+	};
+	for entry in entries.flatten()
+	{
+		// Build the names by hand so they match the names assets are cached
+		// under, whatever the platform's separator is.
+		let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+		let metadata = match entry.metadata()
+		{
+			Ok(metadata) => metadata,
+			Err(_) => continue,
+		};
+		if metadata.is_dir()
+		{
+			scan(&name, files);
+		}
+		else if let Ok(mtime) = metadata.modified()
+		{
+			files.push((name, mtime));
+		}
+	}
+}
+
+impl FileWatcher
+{
+	pub fn new(root: &str) -> Self
+	{
+		let mut watcher = Self {
+			root: root.to_string(),
+			mtimes: HashMap::new(),
+			next_check: 0.,
+		};
+		// Everything is new on the first scan, so ignore it.
+		watcher.poll(0.);
+		watcher
+	}
+
+	/// Returns the files that changed since the last check. The tree is only
+	/// scanned about once a second, since that involves a lot of system calls.
+	pub fn poll(&mut self, time: f64) -> Vec<String>
+	{
+		let mut changed = vec![];
+		if time < self.next_check
+		{
+			return changed;
+		}
+		self.next_check = time + 1.;
+
+		let mut files = vec![];
+		scan(&self.root, &mut files);
+		for (name, mtime) in files
+		{
+			if self.mtimes.insert(name.clone(), mtime) != Some(mtime)
+			{
+				changed.push(name);
+			}
+		}
+		changed
+	}
+}
+
+#[test]
+fn test_file_watcher()
+{
+	use std::time::Duration;
+
+	let root = std::env::temp_dir().join("file_watcher_test");
+	let _ = fs::remove_dir_all(&root);
+	fs::create_dir_all(root.join("sub")).unwrap();
+	let old_file = root.join("sub").join("old.cfg");
+	fs::write(&old_file, "a").unwrap();
+
+	// Files that already exist aren't reported.
+	let root_name = root.to_str().unwrap();
+	let mut watcher = FileWatcher::new(root_name);
+	assert!(watcher.poll(1.).is_empty());
+
+	let mtime = SystemTime::now() + Duration::from_secs(10);
+	fs::File::options()
+		.write(true)
+		.open(&old_file)
+		.unwrap()
+		.set_modified(mtime)
+		.unwrap();
+	fs::write(root.join("new.cfg"), "b").unwrap();
+	// Too soon after the last check.
+	assert!(watcher.poll(1.5).is_empty());
+
+	let mut changed = watcher.poll(2.);
+	changed.sort();
+	assert_eq!(
+		changed,
+		vec![
+			format!("{}/new.cfg", root_name),
+			format!("{}/sub/old.cfg", root_name)
+		]
+	);
+	assert!(watcher.poll(3.).is_empty());
+	fs::remove_dir_all(&root).unwrap();
+}
@@ -3,15 +3,20 @@
 #![allow(dead_code)]
 
 mod atlas;
+mod camera;
 //~ mod character_sprite_sheet;
 mod components;
 mod controls;
 mod error;
 mod game_state;
+mod hot_reload;
 mod map;
 mod menu;
+mod net;
+mod particles;
+mod post_process;
 mod sfx;
-//~ mod spatial_grid;
+mod spatial_grid;
 mod sprite;
 mod ui;
 mod utils;
@@ -23,36 +28,10 @@ use allegro_sys::*;
 use rand::prelude::*;
 use serde_derive::{Deserialize, Serialize};
 use std::rc::Rc;
-use std::sync;
-
-fn make_teleport_shader(disp: &mut Display) -> Result<sync::Weak<Shader>>
-{
-	let shader = disp.create_shader(ShaderPlatform::GLSL).unwrap();
-
-	shader
-		.upgrade()
-		.unwrap()
-		.attach_shader_source(
-			ShaderType::Vertex,
-			Some(&utils::read_to_string("data/teleport_vertex.glsl")?),
-		)
-		.unwrap();
-
-	shader
-		.upgrade()
-		.unwrap()
-		.attach_shader_source(
-			ShaderType::Pixel,
-			Some(&utils::read_to_string("data/teleport_pixel.glsl")?),
-		)
-		.unwrap();
-	shader.upgrade().unwrap().build().unwrap();
-	Ok(shader)
-}
 
 enum CurScreen
 {
-	Game(map::Map),
+	Game(Box<map::Map>),
 	Menu(menu::Menu),
 }
 
@@ -79,20 +58,32 @@ fn real_main() -> Result<()>
 	let mut display = Display::new(&state.core, state.options.width, state.options.height)
 		.map_err(|_| "Couldn't create display".to_string())?;
 
-	let buffer_width = 800;
-	let buffer_height = 600;
-
-	let teleport_shader = make_teleport_shader(&mut display)?;
-	let buffer1 = Bitmap::new(&state.core, buffer_width, buffer_height).unwrap();
-	let buffer2 = Bitmap::new(&state.core, buffer_width, buffer_height).unwrap();
+	let mut buffer_width = state.options.buffer_width;
+	let mut buffer_height = state.options.buffer_height;
+
+	let mut post_process = post_process::PostProcess::new(
+		"data/post_process.cfg",
+		&mut display,
+		&state.core,
+		buffer_width,
+		buffer_height,
+	)?;
+	let mut buffer1 = Bitmap::new(&state.core, buffer_width, buffer_height)
+		.map_err(|_| "Couldn't create buffer".to_string())?;
+	let mut watcher = if state.options.dev_mode
+	{
+		Some(hot_reload::FileWatcher::new("data"))
+	}
+	else
+	{
+		None
+	};
 
 	state.display_width = display.get_width() as f32;
 	state.display_height = display.get_height() as f32;
-	state.draw_scale = utils::min(
-		(display.get_width() as f32) / (buffer_width as f32),
-		(display.get_height() as f32) / (buffer_height as f32),
-	)
-	.floor();
+	state.buffer_width = buffer_width as f32;
+	state.buffer_height = buffer_height as f32;
+	state.update_draw_scale();
 
 	let timer = Timer::new(&state.core, utils::DT as f64)
 		.map_err(|_| "Couldn't create timer".to_string())?;
@@ -112,6 +103,10 @@ fn real_main() -> Result<()>
 			.get_mouse_event_source()
 			.expect("Couldn't get mouse"),
 	);
+	if let Some(joystick_source) = state.core.get_joystick_event_source()
+	{
+		queue.register_event_source(joystick_source);
+	}
 	queue.register_event_source(timer.get_event_source());
 
 	let mut quit = false;
@@ -134,25 +129,36 @@ fn real_main() -> Result<()>
 	let mut old_fullscreen = state.options.fullscreen;
 	let mut old_mouse_hide = state.hide_mouse;
 	let mut prev_frame_start = state.core.get_time();
+	let mut last_tick_time = state.core.get_time();
 
 	timer.start();
 	while !quit
 	{
 		if draw && queue.is_empty()
 		{
-			if state.display_width != display.get_width() as f32
-				|| state.display_height != display.get_height() as f32
+			state.display_width = display.get_width() as f32;
+			state.display_height = display.get_height() as f32;
+			state.update_draw_scale();
+
+			let frame_start = state.core.get_time();
+			// Draw between the last two logic ticks, so motion stays smooth
+			// whatever the refresh rate.
+			state.interpolation = if state.paused
 			{
-				state.display_width = display.get_width() as f32;
-				state.display_height = display.get_height() as f32;
-				state.draw_scale = utils::min(
-					(display.get_width() as f32) / (buffer_width as f32),
-					(display.get_height() as f32) / (buffer_height as f32),
+				1.
+			}
+			else
+			{
+				utils::clamp(
+					((frame_start - last_tick_time) / utils::DT as f64) as f32,
+					0.,
+					1.,
 				)
-				.floor();
+			};
+			if let CurScreen::Game(map) = &mut cur_screen
+			{
+				map.update_terrain_chunks(&state)?;
 			}
-
-			let frame_start = state.core.get_time();
 			state.core.set_target_bitmap(Some(&buffer1));
 
 			match &mut cur_screen
@@ -166,29 +172,15 @@ fn real_main() -> Result<()>
 				state.core.wait_for_vsync().ok();
 			}
 
-			state.core.set_target_bitmap(Some(&buffer2));
-
-			state
-				.core
-				.use_shader(Some(&*teleport_shader.upgrade().unwrap()))
-				.unwrap();
 			state
-				.core
-				.set_shader_uniform(
-					"bitmap_dims",
-					&[[buffer1.get_width() as f32, buffer1.get_height() as f32]][..],
-				)
-				.ok();
-			state
-				.core
-				.set_shader_uniform("swirl_amount", &[state.swirl_amount][..])
-				.ok();
-
-			state.core.draw_bitmap(&buffer1, 0., 0., Flag::zero());
+				.shader_uniforms
+				.insert("swirl_amount".into(), state.swirl_amount);
+			let output = post_process.apply(&buffer1, &state.shader_uniforms, &state.core);
 
 			state.core.set_target_bitmap(Some(display.get_backbuffer()));
 
-			state.core.clear_to_color(Color::from_rgb_f(0., 0., 0.));
+			let [r, g, b] = state.options.letterbox_color;
+			state.core.clear_to_color(Color::from_rgb_f(r, g, b));
 
 			let bw = buffer_width as f32;
 			let bh = buffer_height as f32;
@@ -196,7 +188,7 @@ fn real_main() -> Result<()>
 			let dh = display.get_height() as f32;
 
 			state.core.draw_scaled_bitmap(
-				&buffer2,
+				output,
 				0.,
 				0.,
 				bw,
@@ -212,7 +204,18 @@ fn real_main() -> Result<()>
 
 			if state.tick % 120 == 0
 			{
-				println!("FPS: {:.2}", 1. / (frame_start - prev_frame_start));
+				match &cur_screen
+				{
+					CurScreen::Game(map) => println!(
+						"FPS: {:.2} ({})",
+						1. / (frame_start - prev_frame_start),
+						map.get_draw_stats()
+					),
+					CurScreen::Menu(_) =>
+					{
+						println!("FPS: {:.2}", 1. / (frame_start - prev_frame_start))
+					}
+				}
 			}
 			prev_frame_start = frame_start;
 			logics_without_draw = 0;
@@ -228,18 +231,36 @@ fn real_main() -> Result<()>
 		match event
 		{
 			Event::DisplayClose { .. } => quit = true,
+			Event::KeyDown {
+				keycode: KeyCode::F5,
+				..
+			} if state.options.dev_mode =>
+			{
+				if let Err(e) = post_process.reload(&mut display)
+				{
+					println!("{}", e);
+				}
+			}
+			Event::JoystickConfiguration { .. } =>
+			{
+				state
+					.core
+					.reconfigure_joysticks()
+					.map_err(|_| "Couldn't reconfigure joysticks".to_string())?;
+			}
 			Event::DisplayResize { .. } =>
 			{
 				display
 					.acknowledge_resize()
 					.map_err(|_| "Couldn't acknowledge resize".to_string())?;
 			}
-			Event::TimerTick { .. } =>
+			Event::TimerTick { timestamp, .. } =>
 			{
 				if logics_without_draw > 10
 				{
 					continue;
 				}
+				last_tick_time = timestamp;
 
 				if next_screen.is_none()
 				{
@@ -272,12 +293,62 @@ fn real_main() -> Result<()>
 					old_fullscreen = state.options.fullscreen;
 				}
 
+				if buffer_width != state.options.buffer_width
+					|| buffer_height != state.options.buffer_height
+				{
+					buffer_width = state.options.buffer_width;
+					buffer_height = state.options.buffer_height;
+					buffer1 = Bitmap::new(&state.core, buffer_width, buffer_height)
+						.map_err(|_| "Couldn't create buffer".to_string())?;
+					post_process.resize(&state.core, buffer_width, buffer_height)?;
+					state.buffer_width = buffer_width as f32;
+					state.buffer_height = buffer_height as f32;
+					state.update_draw_scale();
+					match &mut cur_screen
+					{
+						CurScreen::Game(map) =>
+						{
+							map.resize(&state, state.buffer_width, state.buffer_height)
+						}
+						CurScreen::Menu(menu) =>
+						{
+							menu.resize(&state, state.buffer_width, state.buffer_height)
+						}
+					}
+				}
+
+				if let Some(watcher) = watcher.as_mut()
+				{
+					let changed = watcher.poll(state.core.get_time());
+					if !changed.is_empty()
+					{
+						state.reload_files(&changed);
+						if let CurScreen::Game(map) = &mut cur_screen
+						{
+							if changed.iter().any(|f| f.starts_with("data/terrain."))
+							{
+								map.invalidate_terrain();
+							}
+						}
+						if changed
+							.iter()
+							.any(|f| f.ends_with(".glsl") || f == "data/post_process.cfg")
+						{
+							if let Err(e) = post_process.reload(&mut display)
+							{
+								println!("{}", e);
+							}
+						}
+					}
+				}
+
 				logics_without_draw += 1;
 				state.sfx.update_sounds()?;
 
 				if !state.paused
 				{
 					state.tick += 1;
+					state.game_time += state.dt() as f64;
 				}
 				draw = true;
 			}
@@ -293,13 +364,13 @@ fn real_main() -> Result<()>
 					restart_music,
 				} =>
 				{
-					cur_screen = CurScreen::Game(map::Map::new(
+					cur_screen = CurScreen::Game(Box::new(map::Map::new(
 						&mut state,
 						buffer_width as f32,
 						buffer_height as f32,
 						seed,
 						restart_music,
-					)?);
+					)?));
 				}
 				game_state::NextScreen::Menu =>
 				{
@@ -323,30 +394,26 @@ fn real_main() -> Result<()>
 allegro_main! {
 	use std::panic::catch_unwind;
 
-	match catch_unwind(|| real_main().unwrap())
+	if let Err(e) = catch_unwind(|| real_main().unwrap())
 	{
-		Err(e) =>
+		let err: String = e
+			.downcast_ref::<&'static str>()
+			.map(|&e| e.to_owned())
+			.or_else(|| e.downcast_ref::<String>().cloned())
+			.unwrap_or("Unknown error!".to_owned());
+
+		let mut lines = vec![];
+		for line in err.lines().take(10)
 		{
-			let err: String = e
-				.downcast_ref::<&'static str>()
-				.map(|&e| e.to_owned())
-				.or_else(|| e.downcast_ref::<String>().map(|e| e.clone()))
-				.unwrap_or("Unknown error!".to_owned());
-
-			let mut lines = vec![];
-			for line in err.lines().take(10)
-			{
-				lines.push(line.to_string());
-			}
-			show_native_message_box(
-				None,
-				"Error!",
-				"An error has occurred!",
-				&lines.join("\n"),
-				Some("You make me sad."),
-				MESSAGEBOX_ERROR,
-			);
+			lines.push(line.to_string());
 		}
-		Ok(_) => (),
+		show_native_message_box(
+			None,
+			"Error!",
+			"An error has occurred!",
+			&lines.join("\n"),
+			Some("You make me sad."),
+			MESSAGEBOX_ERROR,
+		);
 	}
 }
@@ -1,5 +1,8 @@
 use crate::error::Result;
-use crate::{atlas, components as comps, controls, game_state, sprite, ui, utils};
+use crate::{
+	atlas, camera, components as comps, controls, game_state, net, particles, spatial_grid, sprite,
+	ui, utils,
+};
 
 use allegro::*;
 use allegro_audio::*;
@@ -10,6 +13,8 @@ use na::{
 };
 use nalgebra as na;
 use rand::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
 use utils::ColorExt;
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -20,6 +25,17 @@ enum UIState
 	InMenu,
 }
 
+// For each view rotation, the cell corners that end up at the top, right, left
+// and bottom of the screen, as offsets from the cell's origin.
+const TILE_CORNERS: [[(i32, i32); 4]; 4] = [
+	[(0, 0), (1, 0), (0, 1), (1, 1)],
+	[(0, 1), (0, 0), (1, 1), (1, 0)],
+	[(1, 1), (0, 1), (1, 0), (0, 0)],
+	[(1, 0), (1, 1), (0, 0), (0, 1)],
+];
+
+/// `vals` are the heights of the cell corners relative to `z`, in the order
+/// they appear on screen (see `TILE_CORNERS`).
 fn decode_tile(vals: [i32; 4], x: i32, y: i32, z: i32) -> i32
 {
 	let offt = if z == 0 { 21 } else { 0 };
@@ -294,18 +310,234 @@ fn lower_heightmap(heightmap: &[i32]) -> Vec<i32>
 	res
 }
 
-fn world_to_screen(pos: Point3<f32>) -> Point2<f32>
+/// Rotates the `x`/`y` axes by `rotation` quarter turns, to line them up with
+/// a rotated view.
+fn rotate_xy(x: f32, y: f32, rotation: i32) -> (f32, f32)
 {
-	Point2::new(
-		pos.x as f32 * 64. - pos.y as f32 * 64.,
-		pos.x * 32. + pos.y * 32. - pos.z * 24.,
-	)
+	match rotation.rem_euclid(4)
+	{
+		0 => (x, y),
+		1 => (-y, x),
+		2 => (-x, -y),
+		_ => (y, -x),
+	}
 }
 
-fn spawn_player(pos: Point3<f32>, dir: f32, world: &mut hecs::World) -> hecs::Entity
+fn world_to_screen(pos: Point3<f32>, rotation: i32) -> Point2<f32>
 {
-	world.spawn((
-		comps::Position { pos: pos, dir: dir },
+	let (x, y) = rotate_xy(pos.x, pos.y, rotation);
+	Point2::new(x * 64. - y * 64., x * 32. + y * 32. - pos.z * 24.)
+}
+
+/// Which diagonal row of the rotated view the terrain cell under `pos` is in.
+/// Rows are drawn back to front in increasing order.
+fn cell_depth(pos: Point2<f32>, rotation: i32) -> i32
+{
+	let (top_x, top_y) = TILE_CORNERS[rotation.rem_euclid(4) as usize][0];
+	// Heightmap corner `i` is at `i - 0.5` in the world.
+	let (u, v) = rotate_xy(
+		(pos.x + 0.5).floor() + top_x as f32 - 0.5,
+		(pos.y + 0.5).floor() + top_y as f32 - 0.5,
+		rotation,
+	);
+	(u + v).round() as i32
+}
+
+/// Where the terrain tile sprite of the cell `x`, `y` is drawn (before shifting
+/// by the camera), if its lowest corner is at height `z`.
+fn tile_position(x: i32, y: i32, z: i32, rotation: i32) -> Point2<f32>
+{
+	// Heightmap corner `i` is at `i - 0.5` in the world.
+	let (top_x, top_y) = TILE_CORNERS[rotation as usize][0];
+	let xy = world_to_screen(
+		Point3::new((x + top_x) as f32 - 0.5, (y + top_y) as f32 - 0.5, z as f32),
+		rotation,
+	);
+	// Where the top corner is in the sprite.
+	xy - utils::Vec2D::new(64., 64.)
+}
+
+/// The corners of the part of the (unshifted) screen a view shows.
+fn view_bounds(
+	camera: &camera::Camera, view_width: f32, view_height: f32,
+) -> (Point2<f32>, Point2<f32>)
+{
+	let camera_xy = world_to_screen(camera.pos, camera.rotation) + camera.look_offset;
+	let half_size = Vector2::new(view_width, view_height) / (2. * camera.zoom);
+	(camera_xy - half_size, camera_xy + half_size)
+}
+
+// Burning mushrooms light up the ground this many cells around them.
+const FIRE_LIGHT_RADIUS: f32 = 3.;
+
+/// How bright the sun is at `hour`, from 0 at night to 1 during the day.
+fn get_daylight(hour: f32) -> f32
+{
+	let sun = -(hour / 24. * 2. * f32::pi()).cos();
+	utils::clamp((sun + 0.3) / 0.6, 0., 1.)
+}
+
+/// The tint everything is drawn with, for the given daylight.
+fn get_ambient_light(daylight: f32) -> Color
+{
+	let night = Color::from_rgb_f(0.2, 0.25, 0.45);
+	let light = night.interpolate(Color::from_rgb_f(1., 1., 1.), daylight);
+	// Redden the light around dawn and dusk.
+	let dusk = 4. * daylight * (1. - daylight);
+	light.modulate(Color::from_rgb_f(1., 1. - 0.15 * dusk, 1. - 0.35 * dusk))
+}
+
+/// Adds the light of the `fires` around `pos` to `ambient`.
+fn get_light(pos: Point3<f32>, ambient: Color, fires: &[Point3<f32>]) -> Color
+{
+	let (mut r, mut g, mut b, _) = ambient.to_rgba_f();
+	for fire in fires
+	{
+		let f = 1. - (pos - fire).norm() / FIRE_LIGHT_RADIUS;
+		if f > 0.
+		{
+			r += f;
+			g += 0.6 * f;
+			b += 0.3 * f;
+		}
+	}
+	Color::from_rgb_f(utils::min(r, 1.), utils::min(g, 1.), utils::min(b, 1.))
+}
+
+fn is_in_view(
+	(min, max): (Point2<f32>, Point2<f32>), view_min: Point2<f32>, view_max: Point2<f32>,
+) -> bool
+{
+	min.x < view_max.x && max.x > view_min.x && min.y < view_max.y && max.y > view_min.y
+}
+
+// Cells per side of a terrain chunk.
+const CHUNK_SIZE: i32 = 4;
+// How many bytes of chunk bitmaps are kept, the least recently drawn are
+// dropped first.
+const MAX_CHUNK_BYTES: usize = 64 << 20;
+
+// View rotation, and the chunk's position in the rotated view.
+type ChunkKey = (i32, i32, i32);
+
+// Terrain tiles drawn ahead of time into a bitmap, since the terrain rarely
+// changes.
+struct TerrainChunk
+{
+	bitmap: Bitmap,
+	// The corners of the chunk in unshifted screen space.
+	bounds: (Point2<f32>, Point2<f32>),
+	num_tiles: i32,
+	// The tiles that can hide what is behind them, see `is_occluding_tile`.
+	occluders: Vec<(i32, i32)>,
+	last_used: i64,
+}
+
+impl TerrainChunk
+{
+	fn num_bytes(&self) -> usize
+	{
+		// Chunk bitmaps are RGBA.
+		4 * (self.bitmap.get_width() * self.bitmap.get_height()) as usize
+	}
+}
+
+/// What view culling skipped in the last frame, for the FPS output.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct DrawStats
+{
+	pub tiles_drawn: i32,
+	pub tiles_total: i32,
+	pub sprites_drawn: i32,
+	pub sprites_total: i32,
+	// Seconds spent drawing the views.
+	pub draw_time: f64,
+}
+
+impl DrawStats
+{
+	fn add(&mut self, other: &DrawStats)
+	{
+		self.tiles_drawn += other.tiles_drawn;
+		self.tiles_total += other.tiles_total;
+		self.sprites_drawn += other.sprites_drawn;
+		self.sprites_total += other.sprites_total;
+		self.draw_time += other.draw_time;
+	}
+
+	/// Estimates the time culling saved, assuming every tile and sprite takes
+	/// as long to draw.
+	pub fn saved_time(&self) -> f64
+	{
+		let drawn = self.tiles_drawn + self.sprites_drawn;
+		let total = self.tiles_total + self.sprites_total;
+		if drawn == 0
+		{
+			0.
+		}
+		else
+		{
+			self.draw_time / drawn as f64 * (total - drawn) as f64
+		}
+	}
+}
+
+impl fmt::Display for DrawStats
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(
+			f,
+			"tiles {}/{}, sprites {}/{}, culling saved ~{:.2} ms",
+			self.tiles_drawn,
+			self.tiles_total,
+			self.sprites_drawn,
+			self.sprites_total,
+			self.saved_time() * 1000.
+		)
+	}
+}
+
+// Things drawn on top of the ground, interleaved with the terrain tiles that
+// can hide them.
+enum ViewItem
+{
+	Tile
+	{
+		x: i32, y: i32
+	},
+	Shadow
+	{
+		xy: Point2<f32>, size: i32
+	},
+	Sprite
+	{
+		xy: Point2<f32>,
+		sprite: String,
+		variant: i32,
+		light: Color,
+		scale: f32,
+	},
+}
+
+fn spawn_plane(
+	pos: Point3<f32>, dir: f32, wingman: bool, world: &mut hecs::World,
+) -> Result<hecs::Entity>
+{
+	let exhaust_offsets = if wingman
+	{
+		vec![Vector3::new(-0.3, 0., -0.4)]
+	}
+	else
+	{
+		vec![
+			Vector3::new(-0.3, 0.2, -0.4),
+			Vector3::new(-0.3, -0.2, -0.4),
+		]
+	};
+	let plane = world.spawn((
+		comps::Position { pos, dir },
+		comps::PrevPosition { pos, dir },
 		comps::Velocity {
 			vel: Vector3::zeros(),
 			dir_vel: 0.,
@@ -317,24 +549,15 @@ fn spawn_player(pos: Point3<f32>, dir: f32, world: &mut hecs::World) -> hecs::En
 			},
 		},
 		comps::ParticleSpawners {
-			spawners: vec![
-				comps::ParticleSpawner {
-					offset: Vector3::new(-0.3, 0.2, -0.4),
-					kind: comps::ParticleKind::Stationary,
-					spawn_delay: 0.15,
-					time_to_spawn: 0.,
-					duration: 1.,
-					sprite: "data/engine_particles.cfg".to_string(),
-				},
-				comps::ParticleSpawner {
-					offset: Vector3::new(-0.3, -0.2, -0.4),
-					kind: comps::ParticleKind::Stationary,
-					spawn_delay: 0.15,
+			spawners: exhaust_offsets
+				.into_iter()
+				.map(|offset| comps::ParticleSpawner {
+					offset,
+					emitter: "data/exhaust_particles.cfg".to_string(),
 					time_to_spawn: 0.,
-					duration: 1.,
-					sprite: "data/engine_particles.cfg".to_string(),
-				},
-			],
+					started: false,
+				})
+				.collect(),
 		},
 		comps::CastsShadow { size: 1 },
 		comps::ExplodeOnCollision {
@@ -353,38 +576,81 @@ fn spawn_player(pos: Point3<f32>, dir: f32, world: &mut hecs::World) -> hecs::En
 			time_to_drop: 0.,
 			water_amount: 20,
 		},
-	))
+		comps::Health {
+			health: 100.,
+			max_health: 100.,
+		},
+	));
+	if wingman
+	{
+		world.insert_one(plane, comps::Wingman { refilling: false })?;
+	}
+	else
+	{
+		world.insert_one(
+			plane,
+			comps::Fuel {
+				fuel: 100.,
+				max_fuel: 100.,
+			},
+		)?;
+	}
+	Ok(plane)
 }
 
 fn spawn_particle(
-	pos: Point3<f32>, vel: Vector3<f32>, sprite: String, creation_time: f64, duration: f64,
-	world: &mut hecs::World,
-) -> hecs::Entity
+	pos: Point3<f32>, vel: Vector3<f32>, name: &str, emitter: &particles::Emitter,
+	creation_time: f64, world: &mut hecs::World,
+) -> Result<hecs::Entity>
 {
-	world.spawn((
-		comps::Position { pos: pos, dir: 0. },
-		comps::Velocity {
-			vel: vel,
-			dir_vel: 0.,
-		},
+	let particle = world.spawn((
+		comps::Position { pos, dir: 0. },
+		comps::PrevPosition { pos, dir: 0. },
+		comps::Velocity { vel, dir_vel: 0. },
 		comps::TimeToDie {
-			time_to_die: creation_time + duration,
+			time_to_die: creation_time + emitter.lifetime,
 		},
-		comps::Drawable {
-			kind: comps::DrawableKind::Animated {
-				sprite: sprite,
-				start_time: creation_time,
-				total_duration: duration,
-				once: true,
-			},
+		comps::Particle {
+			emitter: name.to_string(),
+			creation_time,
 		},
-	))
+	));
+	if let Some(sprite) = &emitter.sprite
+	{
+		world.insert_one(
+			particle,
+			comps::Drawable {
+				kind: comps::DrawableKind::Animated {
+					sprite: sprite.clone(),
+					start_time: creation_time,
+					total_duration: emitter.lifetime,
+					once: true,
+				},
+			},
+		)?;
+	}
+	if let Some(trail) = &emitter.trail
+	{
+		world.insert_one(
+			particle,
+			comps::ParticleSpawners {
+				spawners: vec![comps::ParticleSpawner {
+					offset: Vector3::zeros(),
+					emitter: trail.clone(),
+					time_to_spawn: 0.,
+					started: false,
+				}],
+			},
+		)?;
+	}
+	Ok(particle)
 }
 
 fn spawn_cloud(pos: Point3<f32>, world: &mut hecs::World) -> hecs::Entity
 {
 	world.spawn((
-		comps::Position { pos: pos, dir: 0. },
+		comps::Position { pos, dir: 0. },
+		comps::PrevPosition { pos, dir: 0. },
 		comps::Velocity {
 			vel: Vector3::new(0.1, 0.2, 0.),
 			dir_vel: 0.,
@@ -404,7 +670,7 @@ fn spawn_mushroom(pos: Point3<f32>, world: &mut hecs::World) -> hecs::Entity
 {
 	let mut rng = thread_rng();
 	world.spawn((
-		comps::Position { pos: pos, dir: 0. },
+		comps::Position { pos, dir: 0. },
 		comps::Drawable {
 			kind: comps::DrawableKind::Fixed {
 				sprite: "data/mushroom.cfg".to_string(),
@@ -419,14 +685,31 @@ fn spawn_mushroom(pos: Point3<f32>, world: &mut hecs::World) -> hecs::Entity
 fn spawn_obelisk(pos: Point3<f32>, dest: Point3<f32>, world: &mut hecs::World) -> hecs::Entity
 {
 	world.spawn((
-		comps::Position { pos: pos, dir: 0. },
+		comps::Position { pos, dir: 0. },
 		comps::Drawable {
 			kind: comps::DrawableKind::Fixed {
 				sprite: "data/obelisk.cfg".to_string(),
 				variant: 0,
 			},
 		},
-		comps::Obelisk { dest: dest },
+		comps::Obelisk { dest },
+	))
+}
+
+// The fastest descent a plane can touch down on an airstrip at.
+const LANDING_SPEED: f32 = 2.;
+
+// How many seconds ahead wingmen look for terrain. This is fixed rather than
+// an option so that every peer steers them the same way.
+const WINGMAN_LOOK_AHEAD: f32 = 1.;
+
+fn spawn_airstrip(
+	pos: Point3<f32>, half_extents: Vector2<f32>, world: &mut hecs::World,
+) -> hecs::Entity
+{
+	world.spawn((
+		comps::Position { pos, dir: 0. },
+		comps::Airstrip { half_extents },
 	))
 }
 
@@ -448,11 +731,9 @@ fn change_on_fire(mushroom: hecs::Entity, on_fire: bool, world: &mut hecs::World
 				comps::ParticleSpawners {
 					spawners: vec![comps::ParticleSpawner {
 						offset: Vector3::new(0., 0., 1.),
-						kind: comps::ParticleKind::Fire,
-						spawn_delay: 0.15,
+						emitter: "data/fire_particles.cfg".to_string(),
 						time_to_spawn: 0.,
-						duration: 1.,
-						sprite: "data/fire.cfg".to_string(),
+						started: false,
 					}],
 				},
 			)?;
@@ -468,7 +749,7 @@ fn change_on_fire(mushroom: hecs::Entity, on_fire: bool, world: &mut hecs::World
 fn spawn_splash(pos: Point3<f32>, creation_time: f64, world: &mut hecs::World) -> hecs::Entity
 {
 	world.spawn((
-		comps::Position { pos: pos, dir: 0. },
+		comps::Position { pos, dir: 0. },
 		comps::Drawable {
 			kind: comps::DrawableKind::Animated {
 				sprite: "data/splash.cfg".to_string(),
@@ -488,11 +769,9 @@ fn spawn_water_blob(
 ) -> hecs::Entity
 {
 	world.spawn((
-		comps::Position { pos: pos, dir: 0. },
-		comps::Velocity {
-			vel: vel,
-			dir_vel: 0.,
-		},
+		comps::Position { pos, dir: 0. },
+		comps::PrevPosition { pos, dir: 0. },
+		comps::Velocity { vel, dir_vel: 0. },
 		comps::AffectedByGravity,
 		comps::AffectedByFriction,
 		comps::Drawable {
@@ -520,8 +799,8 @@ fn spawn_water_blob(
 
 fn spawn_explosion(pos: Point3<f32>, creation_time: f64, world: &mut hecs::World) -> hecs::Entity
 {
-	let explosion = world.spawn((
-		comps::Position { pos: pos, dir: 0. },
+	world.spawn((
+		comps::Position { pos, dir: 0. },
 		comps::Drawable {
 			kind: comps::DrawableKind::Animated {
 				sprite: "data/explosion.cfg".to_string(),
@@ -533,39 +812,15 @@ fn spawn_explosion(pos: Point3<f32>, creation_time: f64, world: &mut hecs::World
 		comps::TimeToDie {
 			time_to_die: creation_time + 0.5,
 		},
-	));
-
-	let mut rng = thread_rng();
-	for _ in 0..5
-	{
-		world.spawn((
-			comps::Position { pos: pos, dir: 0. },
-			comps::Velocity {
-				vel: Vector3::new(
-					rng.gen_range(-2.0..2.0),
-					rng.gen_range(-2.0..2.0),
-					rng.gen_range(3.0..5.0),
-				),
-				dir_vel: 0.,
-			},
-			comps::AffectedByGravity,
-			comps::ParticleSpawners {
-				spawners: vec![comps::ParticleSpawner {
-					offset: Vector3::new(0., 0., 0.),
-					kind: comps::ParticleKind::Stationary,
-					spawn_delay: 0.1,
-					time_to_spawn: 0.,
-					duration: 1.,
-					sprite: "data/engine_particles.cfg".to_string(),
-				}],
-			},
-			comps::TimeToDie {
-				time_to_die: creation_time + 1.5,
-			},
-		));
-	}
-
-	explosion
+		comps::ParticleSpawners {
+			spawners: vec![comps::ParticleSpawner {
+				offset: Vector3::zeros(),
+				emitter: "data/debris_particles.cfg".to_string(),
+				time_to_spawn: 0.,
+				started: false,
+			}],
+		},
+	))
 }
 
 fn get_height(heightmap: &[i32], pos: Point2<f32>) -> Option<f32>
@@ -578,9 +833,9 @@ fn get_height(heightmap: &[i32], pos: Point2<f32>) -> Option<f32>
 
 	if x >= 0 && y >= 0 && x + 1 < size && y + 1 < size
 	{
-		let h00 = heightmap[((x + 0) + (y + 0) * size) as usize] as f32;
-		let h01 = heightmap[((x + 0) + (y + 1) * size) as usize] as f32;
-		let h10 = heightmap[((x + 1) + (y + 0) * size) as usize] as f32;
+		let h00 = heightmap[(x + y * size) as usize] as f32;
+		let h01 = heightmap[(x + (y + 1) * size) as usize] as f32;
+		let h10 = heightmap[((x + 1) + y * size) as usize] as f32;
 		let h11 = heightmap[((x + 1) + (y + 1) * size) as usize] as f32;
 
 		let h0 = (1. - fy) * h00 + fy * h01;
@@ -594,6 +849,212 @@ fn get_height(heightmap: &[i32], pos: Point2<f32>) -> Option<f32>
 	}
 }
 
+/// Points along the path a plane follows over the next `look_ahead` seconds if it
+/// keeps its velocity and turn rate. The points are close enough together that
+/// every tile crossed gets sampled.
+fn flight_path(
+	pos: Point3<f32>, vel: Vector3<f32>, dir_vel: f32, look_ahead: f32,
+) -> Vec<Point3<f32>>
+{
+	let speed = vel.xy().norm();
+	let heading = vel.y.atan2(vel.x);
+	let num_samples = utils::max(
+		1.,
+		(look_ahead * utils::max(speed, vel.z.abs()) / 0.1).ceil(),
+	) as i32;
+	let mut path = Vec::with_capacity(num_samples as usize + 1);
+	for i in 0..=num_samples
+	{
+		let t = look_ahead * i as f32 / num_samples as f32;
+		let offset = if dir_vel.abs() < 1e-3
+		{
+			t * vel.xy()
+		}
+		else
+		{
+			// Constant speed and turn rate trace out a circular arc.
+			let r = speed / dir_vel;
+			let new_heading = heading + dir_vel * t;
+			Vector2::new(
+				r * (new_heading.sin() - heading.sin()),
+				r * (heading.cos() - new_heading.cos()),
+			)
+		};
+		path.push(Point3::new(
+			pos.x + offset.x,
+			pos.y + offset.y,
+			pos.z + t * vel.z,
+		));
+	}
+	path
+}
+
+/// Where to draw an entity, `alpha` of the way from its position at the
+/// previous tick to `pos`.
+fn interpolate_position(
+	pos: &comps::Position, prev: Option<&comps::PrevPosition>, alpha: f32,
+) -> comps::Position
+{
+	let prev = match prev
+	{
+		Some(prev) => prev,
+		None => return *pos,
+	};
+	// Teleports shouldn't sweep across the map.
+	if (pos.pos - prev.pos).norm() > 1.
+	{
+		return *pos;
+	}
+	let turn = (pos.dir - prev.dir + f32::pi()).rem_euclid(2. * f32::pi()) - f32::pi();
+	comps::Position {
+		pos: prev.pos + (pos.pos - prev.pos) * alpha,
+		dir: prev.dir + turn * alpha,
+	}
+}
+
+/// Height of the terrain plus any mushroom standing on it.
+fn get_obstacle_height(
+	heightmap: &[i32], mushrooms: &[Option<hecs::Entity>], pos: Point2<f32>,
+) -> Option<f32>
+{
+	let mushroom_height = get_mushroom(mushrooms, pos).map(|_| 2.).unwrap_or(0.);
+	get_height(heightmap, pos).map(|h| h + mushroom_height)
+}
+
+/// Fractions of the way from `a` to `b` where the obstacle height under the
+/// segment, minus `climb` times the fraction, can peak. Within a tile the terrain
+/// is quadratic along a line, so this is the ends, either side of every tile edge
+/// crossed and the peaks of those quadratics.
+fn segment_peaks(
+	heightmap: &[i32], mushrooms: &[Option<hecs::Entity>], a: Point2<f32>, b: Point2<f32>,
+	climb: f32,
+) -> Vec<f32>
+{
+	let mut edges = vec![0., 1.];
+	for axis in 0..2
+	{
+		let (start, end) = (a[axis], b[axis]);
+		if start == end
+		{
+			continue;
+		}
+		// Tile edges are halfway between integer coordinates.
+		let first = (utils::min(start, end) + 0.5).ceil() as i32;
+		let last = (utils::max(start, end) + 0.5).floor() as i32;
+		for k in first..=last
+		{
+			let f = (k as f32 - 0.5 - start) / (end - start);
+			if f > 0. && f < 1.
+			{
+				edges.push(f);
+			}
+		}
+	}
+	edges.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+	let height_at = |f: f32| {
+		get_obstacle_height(heightmap, mushrooms, a + f * (b - a)).unwrap_or(0.) - climb * f
+	};
+	let mut peaks = vec![];
+	for pair in edges.windows(2)
+	{
+		let (f0, f1) = (pair[0], pair[1]);
+		if f1 - f0 < 1e-6
+		{
+			continue;
+		}
+		// Stay inside the tile, mushrooms change height at its edges.
+		let eps = utils::min(1e-4, (f1 - f0) / 4.);
+		let (f0, f1) = (f0 + eps, f1 - eps);
+		let (h0, hm, h1) = (height_at(f0), height_at((f0 + f1) / 2.), height_at(f1));
+		peaks.push(f0);
+		peaks.push(f1);
+		let curve = 2. * (h0 - 2. * hm + h1);
+		let slope = -3. * h0 + 4. * hm - h1;
+		if curve < 0.
+		{
+			let u = -slope / (2. * curve);
+			if u > 0. && u < 1.
+			{
+				peaks.push(f0 + u * (f1 - f0));
+			}
+		}
+	}
+	peaks.sort_by(|x, y| x.partial_cmp(y).unwrap());
+	peaks
+}
+
+/// Returns how many seconds until the plane comes within 0.5 units of the
+/// terrain or a mushroom, if it does so within `look_ahead` seconds.
+fn predict_collision(
+	heightmap: &[i32], mushrooms: &[Option<hecs::Entity>], pos: Point3<f32>, vel: Vector3<f32>,
+	dir_vel: f32, look_ahead: f32,
+) -> Option<f32>
+{
+	let path = flight_path(pos, vel, dir_vel, look_ahead);
+	let num_samples = path.len() - 1;
+	for (i, pair) in path.windows(2).enumerate()
+	{
+		let (a, b) = (pair[0], pair[1]);
+		for f in segment_peaks(heightmap, mushrooms, a.xy(), b.xy(), b.z - a.z)
+		{
+			let test_pos = a + f * (b - a);
+			if let Some(h) = get_obstacle_height(heightmap, mushrooms, test_pos.xy())
+			{
+				if test_pos.z - h < 0.5
+				{
+					return Some(look_ahead * (i as f32 + f) / num_samples as f32);
+				}
+			}
+		}
+	}
+	None
+}
+
+/// The highest terrain or mushroom top under the path over the next `look_ahead`
+/// seconds.
+fn terrain_ahead(
+	heightmap: &[i32], mushrooms: &[Option<hecs::Entity>], pos: Point3<f32>, vel: Vector3<f32>,
+	dir_vel: f32, look_ahead: f32,
+) -> f32
+{
+	let path = flight_path(pos, vel, dir_vel, look_ahead);
+	let mut ground = 0.;
+	for pair in path.windows(2)
+	{
+		let (a, b) = (pair[0].xy(), pair[1].xy());
+		for f in segment_peaks(heightmap, mushrooms, a, b, 0.)
+		{
+			if let Some(h) = get_obstacle_height(heightmap, mushrooms, a + f * (b - a))
+			{
+				ground = utils::max(ground, h);
+			}
+		}
+	}
+	ground
+}
+
+fn steer_vertical(vel: &mut comps::Velocity, up_down: f32, water_amount: i32, dt: f32)
+{
+	let max_vert_speed = 3.;
+	let desired_vel = up_down * max_vert_speed;
+	let f = utils::clamp(water_amount as f32 / 50., 0., 1.);
+	let accel = f * 1. + (1. - f) * 5.;
+	if vel.vel.z > desired_vel
+	{
+		vel.vel.z -= accel * dt;
+	}
+	else if vel.vel.z < desired_vel
+	{
+		vel.vel.z += accel * dt;
+	}
+	let z_speed = vel.vel.z.abs();
+	if z_speed > max_vert_speed
+	{
+		vel.vel.z = max_vert_speed.copysign(vel.vel.z);
+	}
+}
+
 fn get_mushroom(mushrooms: &[Option<hecs::Entity>], pos: Point2<f32>) -> Option<hecs::Entity>
 {
 	let size = (mushrooms.len() as f32).sqrt() as i32;
@@ -609,29 +1070,117 @@ fn get_mushroom(mushrooms: &[Option<hecs::Entity>], pos: Point2<f32>) -> Option<
 	}
 }
 
+fn draw_gauge(x: f32, y: f32, f: f32, frame_color: Color, state: &game_state::GameState)
+{
+	let w = 96.;
+	let h = 16.;
+	let color = if f < 0.25
+	{
+		Color::from_rgb_f(0.9, 0.3, 0.3)
+	}
+	else
+	{
+		Color::from_rgb_f(0.4, 0.8, 0.4)
+	};
+	state
+		.prim
+		.draw_filled_rectangle(x, y, x + w * f, y + h, color);
+	state
+		.prim
+		.draw_rectangle(x, y, x + w, y + h, frame_color, 2.);
+}
+
+fn draw_reticle(x: f32, y: f32, offset: utils::Vec2D, state: &game_state::GameState)
+{
+	let r = 64.;
+	let color = Color::from_rgba_f(0.8, 0.6, 0.4, 0.5);
+	state.prim.draw_circle(x, y, r, color, 1.);
+	state.prim.draw_line(x - 4., y, x + 4., y, color, 1.);
+	state.prim.draw_line(x, y - 4., x, y + 4., color, 1.);
+
+	let cx = x + offset.x * r;
+	let cy = y + offset.y * r;
+	let color = Color::from_rgb_f(0.8, 0.6, 0.4);
+	state.prim.draw_circle(cx, cy, 6., color, 2.);
+	state
+		.prim
+		.draw_line(x, y, cx, cy, Color::from_rgba_f(0.8, 0.6, 0.4, 0.5), 1.);
+}
+
+/// What an entry in the entity grid is, so queries can skip the rest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GridKind
+{
+	Obelisk,
+	Fire,
+	Plane,
+}
+
+struct Pilot
+{
+	entity: hecs::Entity,
+	camera: camera::Camera,
+	collision_alert: bool,
+	time_to_play_alert: f64,
+	num_blobs: i32,
+	num_extinguished: i32,
+	// Altitude kept by the auto-level assist.
+	hold_altitude: Option<f32>,
+	auto_dropping: bool,
+	// Whether drop water was held last tick, to spot new presses.
+	drop_held: bool,
+	autopilot: bool,
+}
+
+impl Pilot
+{
+	fn new(entity: hecs::Entity, camera_pos: Point3<f32>, time: f64) -> Self
+	{
+		Self {
+			entity,
+			camera: camera::Camera::new(camera_pos),
+			collision_alert: false,
+			time_to_play_alert: time,
+			num_blobs: 0,
+			num_extinguished: 0,
+			hold_altitude: None,
+			auto_dropping: false,
+			drop_held: false,
+			autopilot: false,
+		}
+	}
+}
+
 pub struct Map
 {
 	heightmap: Vec<i32>,
 	mushrooms: Vec<Option<hecs::Entity>>,
+	lakes: Vec<Point3<f32>>,
 	size: i32,
 	display_width: f32,
 	display_height: f32,
-	camera_pos: Point3<f32>,
 	world: hecs::World,
-	player: hecs::Entity,
+	pilots: Vec<Pilot>,
+	// The pilots shown on this machine.
+	views: Vec<usize>,
+	rng: StdRng,
+	entity_grid: spatial_grid::SpatialGrid<(hecs::Entity, GridKind)>,
+	terrain_chunks: HashMap<ChunkKey, TerrainChunk>,
+	num_draws: i64,
+	// Ticks simulated so far, scaled by slow motion. They stay whole numbers in
+	// network games, so every machine agrees on the time.
+	ticks: f64,
+	// The time of day when the map starts.
+	start_hour: f32,
+	fire_spread_probability: f32,
 	time_to_spread_fire: f64,
 	subscreens: Vec<ui::SubScreen>,
 	ui_state: UIState,
 	seed: u64,
-	collision_alert: bool,
-	time_to_play_alert: f64,
 	obelisk_sound: SampleInstance,
 	num_fires: i32,
-	num_blobs: i32,
-	num_extinguished: i32,
 	show_minimap: bool,
-	old_up: f32,
-	old_down: f32,
+	draw_stats: DrawStats,
 }
 
 impl Map
@@ -648,7 +1197,14 @@ impl Map
 			state.sfx.play_music()?;
 		}
 
-		let size = state.options.map_size;
+		// Network games use the host's level settings, without saving them.
+		let mut options = state.options.clone();
+		if let Some(settings) = state.net.as_ref().and_then(|net| net.get_settings())
+		{
+			settings.apply(&mut options);
+		}
+
+		let size = options.map_size;
 		let real_size = 2i32.pow(size as u32) + 1;
 		let mut rng = StdRng::seed_from_u64(seed);
 
@@ -658,23 +1214,53 @@ impl Map
 		let radius = real_size as f32 / 2.;
 		let player_pos = Point3::new(radius, radius, 0.)
 			+ Vector3::new(radius * dir.cos(), radius * dir.sin(), 12.);
-		let player = spawn_player(player_pos, f32::pi() + dir, &mut world);
-
-		for _ in 0..size * size
+		let (num_players, views) = if let Some(session) = &state.net
 		{
-			spawn_cloud(
-				Point3::new(
-					rng.gen_range(0..real_size) as f32,
-					rng.gen_range(0..real_size) as f32,
-					rng.gen_range(10..15) as f32,
-				),
-				&mut world,
+			(session.num_players() as i32, vec![session.player()])
+		}
+		else
+		{
+			let num_players = utils::max(1, state.options.num_players);
+			(num_players, (0..num_players as usize).collect())
+		};
+		let mut pilots = vec![];
+		for i in 0..num_players
+		{
+			let offset = Rotation2::new(dir) * Vector2::new(0., -1.5 * i as f32);
+			let pos = player_pos + Vector3::new(offset.x, offset.y, 0.);
+			let player = spawn_plane(pos, f32::pi() + dir, false, &mut world)?;
+			pilots.push(Pilot::new(player, pos, 0.));
+		}
+		for i in 0..options.num_wingmen
+		{
+			let side = if i % 2 == 0 { 1. } else { -1. };
+			let offset = Rotation2::new(dir) * Vector2::new(1. + (i / 2) as f32, side * 1.5);
+			spawn_plane(
+				player_pos + Vector3::new(offset.x, offset.y, 0.),
+				f32::pi() + dir,
+				true,
+				&mut world,
+			)?;
+		}
+
+		for _ in 0..size * size
+		{
+			spawn_cloud(
+				Point3::new(
+					rng.gen_range(0..real_size) as f32,
+					rng.gen_range(0..real_size) as f32,
+					rng.gen_range(10..15) as f32,
+				),
+				&mut world,
 			);
 		}
 
 		state.cache_sprite("data/terrain.cfg")?;
 		state.cache_sprite("data/plane.cfg")?;
 		state.cache_sprite("data/engine_particles.cfg")?;
+		state.cache_emitter("data/exhaust_particles.cfg")?;
+		state.cache_emitter("data/fire_particles.cfg")?;
+		state.cache_emitter("data/debris_particles.cfg")?;
 		state.cache_sprite("data/explosion.cfg")?;
 		state.cache_sprite("data/splash.cfg")?;
 		state.cache_sprite("data/water_blob.cfg")?;
@@ -716,7 +1302,7 @@ impl Map
 					}
 				})
 				.sum();
-			if num_water < ((real_size * real_size) as f32 * state.options.water_factor) as i32
+			if num_water < ((real_size * real_size) as f32 * options.water_factor) as i32
 			{
 				for h in &mut heightmap
 				{
@@ -774,7 +1360,7 @@ impl Map
 		}
 
 		let mut num_fires = 0;
-		let target_num_fires = (state.options.fire_start_probability * num_mushrooms as f32) as i32;
+		let target_num_fires = (options.fire_start_probability * num_mushrooms as f32) as i32;
 		let mut visited_mushrooms = 0;
 
 		let mut mushrooms = vec![None; mushroom_map.len()];
@@ -785,8 +1371,7 @@ impl Map
 				let (has_mushroom, h) = mushroom_map[(x + real_size * y) as usize];
 				mushrooms[(x + real_size * y) as usize] = if has_mushroom
 				{
-					let mushroom =
-						spawn_mushroom(Point3::new(x as f32, y as f32, h as f32), &mut world);
+					let mushroom = spawn_mushroom(Point3::new(x as f32, y as f32, h), &mut world);
 					if rng.gen_bool(
 						(target_num_fires - num_fires) as f64
 							/ (num_mushrooms - visited_mushrooms) as f64,
@@ -806,7 +1391,7 @@ impl Map
 		}
 
 		let mut obelisk_locs = vec![];
-		for _ in 0..((size - 3) as f32 * state.options.obelisk_factor) as i32
+		for _ in 0..((size - 3) as f32 * options.obelisk_factor) as i32
 		{
 			'placed: for _ in 0..50
 			{
@@ -815,7 +1400,7 @@ impl Map
 				let h = get_height(&heightmap, Point2::new(x as f32, y as f32)).unwrap();
 
 				if h > 0.5
-					&& !obelisk_locs.iter().any(|&e| e == (x, y))
+					&& !obelisk_locs.contains(&(x, y))
 					&& mushrooms[(x + y * real_size) as usize].is_none()
 				{
 					obelisk_locs.push((x, y));
@@ -825,7 +1410,7 @@ impl Map
 						let dy = rng.gen_range(2..real_size - 2);
 						let h2 = get_height(&heightmap, Point2::new(dx as f32, dy as f32)).unwrap();
 
-						if !obelisk_locs.iter().any(|&e| e == (dx, dy))
+						if !obelisk_locs.contains(&(dx, dy))
 						{
 							spawn_obelisk(
 								Point3::new(x as f32, y as f32, h),
@@ -839,40 +1424,244 @@ impl Map
 			}
 		}
 
+		let mut airstrip_tiles = vec![];
+		let strip_len = 3;
+		for _ in 0..size - 2
+		{
+			'placed_strip: for _ in 0..200
+			{
+				let (len_x, len_y) = if rng.gen_bool(0.5)
+				{
+					(strip_len, 1)
+				}
+				else
+				{
+					(1, strip_len)
+				};
+				let x = rng.gen_range(1..real_size - len_x);
+				let y = rng.gen_range(1..real_size - len_y);
+				let h = heightmap[(x + y * real_size) as usize];
+				if h == 0
+				{
+					continue;
+				}
+				for vy in y..=y + len_y
+				{
+					for vx in x..=x + len_x
+					{
+						if heightmap[(vx + vy * real_size) as usize] != h
+						{
+							continue 'placed_strip;
+						}
+					}
+				}
+				for ty in y..y + len_y
+				{
+					for tx in x..x + len_x
+					{
+						let on_fire = mushrooms[(tx + ty * real_size) as usize].is_some_and(|m| {
+							world.get::<&comps::Mushroom>(m).is_ok_and(|m| m.on_fire)
+						});
+						if on_fire
+							|| obelisk_locs.contains(&(tx, ty))
+							|| airstrip_tiles.contains(&(tx, ty))
+						{
+							continue 'placed_strip;
+						}
+					}
+				}
+				for ty in y..y + len_y
+				{
+					for tx in x..x + len_x
+					{
+						airstrip_tiles.push((tx, ty));
+						if let Some(mushroom) = mushrooms[(tx + ty * real_size) as usize].take()
+						{
+							world.despawn(mushroom)?;
+						}
+					}
+				}
+				spawn_airstrip(
+					Point3::new(
+						x as f32 + (len_x - 1) as f32 / 2.,
+						y as f32 + (len_y - 1) as f32 / 2.,
+						h as f32,
+					),
+					Vector2::new(len_x as f32 / 2., len_y as f32 / 2.),
+					&mut world,
+				);
+				break 'placed_strip;
+			}
+		}
+
+		let mut lakes = vec![];
+		for y in 0..real_size - 1
+		{
+			for x in 0..real_size - 1
+			{
+				let h = get_height(&heightmap, Point2::new(x as f32, y as f32)).unwrap();
+				if h < 0.1
+				{
+					lakes.push(Point3::new(x as f32, y as f32, h));
+				}
+			}
+		}
+
 		print_heightmap(&heightmap);
 
 		Ok(Self {
-			heightmap: heightmap,
-			mushrooms: mushrooms,
+			heightmap,
+			mushrooms,
+			lakes,
 			size: real_size,
-			display_width: display_width,
-			display_height: display_height,
-			camera_pos: player_pos,
-			world: world,
-			player: player,
-			time_to_spread_fire: state.time() + 5.,
+			display_width,
+			display_height,
+			world,
+			pilots,
+			views,
+			rng: StdRng::seed_from_u64(seed),
+			entity_grid: spatial_grid::SpatialGrid::new(2.),
+			terrain_chunks: HashMap::new(),
+			num_draws: 0,
+			ticks: 0.,
+			start_hour: if options.night_mission { 22. } else { 10. },
+			fire_spread_probability: options.fire_spread_probability,
+			time_to_spread_fire: 5.,
 			subscreens: vec![],
 			ui_state: UIState::Regular,
-			seed: seed,
-			collision_alert: false,
-			time_to_play_alert: state.time(),
+			seed,
 			obelisk_sound: state
 				.sfx
 				.play_continuous_sound("data/near_teleport_cont.ogg", 0.)?,
 			num_fires: 0,
-			num_blobs: 0,
-			num_extinguished: 0,
 			show_minimap: false,
-			old_up: 0.,
-			old_down: 0.,
+			draw_stats: DrawStats::default(),
 		})
 	}
 
+	fn assist_input(
+		&mut self, pilot: usize, frame: &mut net::InputFrame, options: &game_state::Options,
+	)
+	{
+		let pilot = &mut self.pilots[pilot];
+		let (pos, vel, dir_vel, water_amount) =
+			match self
+				.world
+				.query_one_mut::<(&comps::Position, &comps::Velocity, &comps::WaterCollector)>(
+					pilot.entity,
+				)
+			{
+				Ok((pos, vel, water_col)) =>
+				{
+					(pos.pos, vel.vel, vel.dir_vel, water_col.water_amount)
+				}
+				Err(_) => return,
+			};
+		if options.auto_drop_water
+		{
+			// One press keeps dropping until the tank is empty, the next one
+			// stops it.
+			let drop_held = net::get_action_state(frame, controls::Action::DropWater) > 0.5;
+			if drop_held && !pilot.drop_held
+			{
+				pilot.auto_dropping = !pilot.auto_dropping;
+			}
+			pilot.drop_held = drop_held;
+			if water_amount == 0
+			{
+				pilot.auto_dropping = false;
+			}
+			if pilot.auto_dropping
+			{
+				net::set_action_state(frame, controls::Action::DropWater, 1.);
+			}
+		}
+
+		let up_down = net::get_action_state(frame, controls::Action::Ascend)
+			- net::get_action_state(frame, controls::Action::Descend);
+		// Manual climbs and descents always override the assists.
+		let target_z = if up_down.abs() > 0.1
+		{
+			pilot.hold_altitude = None;
+			None
+		}
+		else if pilot.autopilot
+		{
+			let ground = terrain_ahead(
+				&self.heightmap,
+				&self.mushrooms,
+				pos,
+				vel,
+				dir_vel,
+				options.collision_look_ahead,
+			);
+			let near_lake = self.lakes.iter().any(|l| (l.xy() - pos.xy()).norm() < 3.);
+			// Skim low enough to scoop until the tank is full, then climb away.
+			if near_lake && water_amount < 99
+			{
+				Some(ground + 1.)
+			}
+			else
+			{
+				Some(ground + options.autopilot_clearance)
+			}
+		}
+		else if options.auto_level
+		{
+			Some(*pilot.hold_altitude.get_or_insert(pos.z))
+		}
+		else
+		{
+			None
+		};
+		if let Some(target_z) = target_z
+		{
+			let correction = utils::clamp(target_z - pos.z, -1., 1.);
+			net::set_action_state(frame, controls::Action::Ascend, utils::max(correction, 0.));
+			net::set_action_state(
+				frame,
+				controls::Action::Descend,
+				utils::max(-correction, 0.),
+			);
+		}
+	}
+
 	pub fn logic(
 		&mut self, state: &mut game_state::GameState,
 	) -> Result<Option<game_state::NextScreen>>
 	{
-		if state.controls.get_action_state(controls::Action::Restart) > 0.5
+		// Interpolation.
+		for (_, (pos, prev)) in self
+			.world
+			.query_mut::<(&comps::Position, &mut comps::PrevPosition)>()
+		{
+			prev.pos = pos.pos;
+			prev.dir = pos.dir;
+		}
+		for pilot in &mut self.pilots
+		{
+			pilot.camera.snapshot();
+		}
+
+		// Slowing down only one machine would desync a network game.
+		state.time_scale = if state.net.is_none()
+		{
+			state.options.game_speed
+		}
+		else
+		{
+			1.
+		};
+		let dt = state.dt();
+		let mut restart = false;
+		for i in 0..self.views.len()
+		{
+			let player_controls = state.get_player_controls_mut(i);
+			player_controls.update();
+			restart |= player_controls.is_held(controls::Action::Restart);
+		}
+		// Restarting a network game alone would desync it.
+		if restart && state.net.is_none()
 		{
 			state.paused = false;
 			return Ok(Some(game_state::NextScreen::Game {
@@ -880,127 +1669,413 @@ impl Map
 				restart_music: false,
 			}));
 		}
-		if self.ui_state != UIState::Regular
+		// Network games keep stepping with the menu open, or the other
+		// pilots would stall.
+		let in_menu = self.ui_state == UIState::InMenu;
+		if self.ui_state == UIState::Victory || (in_menu && state.net.is_none())
 		{
 			return Ok(None);
 		}
 
+		// Assists rewrite the local inputs before they are shared, so every
+		// machine sees the same inputs.
+		let mut local_inputs = vec![];
+		for (i, pilot) in self.views.clone().into_iter().enumerate()
+		{
+			if in_menu
+			{
+				local_inputs.push((pilot, [0.; net::FLIGHT_ACTIONS.len()]));
+				continue;
+			}
+			if state
+				.get_player_controls(i)
+				.is_pressed(controls::Action::Autopilot)
+			{
+				self.pilots[pilot].autopilot = !self.pilots[pilot].autopilot;
+				self.pilots[pilot].hold_altitude = None;
+			}
+			let mut frame = net::input_frame_from_controls(state.get_player_controls(i));
+			if state
+				.get_player_controls(i)
+				.is_held(controls::Action::FreeLook)
+			{
+				// The steering inputs pan the view instead of flying the plane.
+				let get_action_state = |action| net::get_action_state(&frame, action);
+				let dir = Vector2::new(
+					get_action_state(controls::Action::TurnRight)
+						- get_action_state(controls::Action::TurnLeft),
+					get_action_state(controls::Action::Descend)
+						- get_action_state(controls::Action::Ascend),
+				);
+				self.pilots[pilot].camera.free_look(dir, dt);
+				for action in [
+					controls::Action::TurnLeft,
+					controls::Action::TurnRight,
+					controls::Action::Ascend,
+					controls::Action::Descend,
+				]
+				{
+					net::set_action_state(&mut frame, action, 0.);
+				}
+			}
+			self.assist_input(pilot, &mut frame, &state.options);
+			local_inputs.push((pilot, frame));
+		}
+		let mut inputs = vec![[0.; net::FLIGHT_ACTIONS.len()]; self.pilots.len()];
+		if let Some(session) = state.net.as_mut()
+		{
+			match session.step(local_inputs[0].1)?
+			{
+				Some(frame) => inputs = frame,
+				None =>
+				{
+					// Wait for the other pilots to catch up.
+					state.paused = true;
+					return Ok(None);
+				}
+			}
+			state.paused = false;
+		}
+		else
+		{
+			for (pilot, frame) in local_inputs
+			{
+				inputs[pilot] = frame;
+			}
+		}
+
+		let time = self.time();
+		self.ticks += state.time_scale as f64;
 		let mut to_die = vec![];
 
 		// Player input.
-		self.show_minimap = state.controls.get_action_state(controls::Action::Minimap) > 0.5;
-		let mut spawn_water = None;
+		self.show_minimap = false;
+		for i in 0..self.views.len()
+		{
+			self.show_minimap |= state
+				.get_player_controls(i)
+				.is_held(controls::Action::Minimap);
+		}
+		let mut spawn_water = vec![];
 		let mut rng = thread_rng();
+		// Sounds are heard from the first local pilot that is still flying.
 		let mut player_pos = None;
-		let mut player_vel = None;
-		if let Ok((pos, mut vel, mut water_col)) = self.world.query_one_mut::<(
-			&comps::Position,
-			&mut comps::Velocity,
-			&mut comps::WaterCollector,
-		)>(self.player)
+		let mut player_rotation = 0;
+		let mut pilot_states = vec![];
+		for (i, pilot) in self.pilots.iter_mut().enumerate()
 		{
-			let get_action_state =
-				|state: &mut game_state::GameState, action| state.controls.get_action_state(action);
-			player_pos = Some(pos.pos);
-			player_vel = Some(vel.vel);
-			let left_right = get_action_state(state, controls::Action::TurnLeft)
-				- get_action_state(state, controls::Action::TurnRight);
-			let up = get_action_state(state, controls::Action::Ascend);
-			let down = get_action_state(state, controls::Action::Descend);
-			let up_down = up - down;
-			if up > self.old_up
+			let get_action_state = |action| net::get_action_state(&inputs[i], action);
+			let view = self.views.iter().position(|&v| v == i);
+			let (pos, vel, water_col, fuel) = match self.world.query_one_mut::<(
+				&comps::Position,
+				&mut comps::Velocity,
+				&mut comps::WaterCollector,
+				Option<&comps::Fuel>,
+			)>(pilot.entity)
+			{
+				Ok(q) => q,
+				Err(_) => continue,
+			};
+			if player_pos.is_none() && view.is_some()
+			{
+				player_pos = Some(pos.pos);
+				player_rotation = pilot.camera.rotation;
+			}
+			let left_right = get_action_state(controls::Action::TurnLeft)
+				- get_action_state(controls::Action::TurnRight);
+			let up = get_action_state(controls::Action::Ascend);
+			let down = get_action_state(controls::Action::Descend);
+			let out_of_fuel = fuel.map(|f| f.fuel <= 0.).unwrap_or(false);
+			// No engine, so glide down.
+			let up_down = if out_of_fuel
 			{
-				state.sfx.play_sound("data/fly_up.ogg")?;
+				-0.5
 			}
-			if down > self.old_down
+			else
+			{
+				utils::clamp(up - down, -1., 1.)
+			};
+			if let (Some(view), false) = (view, in_menu)
 			{
-				state.sfx.play_sound("data/fly_down.ogg")?;
+				let player_controls = state.get_player_controls(view);
+				let pressed_up = player_controls.is_pressed(controls::Action::Ascend);
+				let pressed_down = player_controls.is_pressed(controls::Action::Descend);
+				if pressed_up && !out_of_fuel
+				{
+					state.sfx.play_sound("data/fly_up.ogg")?;
+				}
+				if pressed_down && !out_of_fuel
+				{
+					state.sfx.play_sound("data/fly_down.ogg")?;
+				}
 			}
-			self.old_up = up;
-			self.old_down = down;
 
 			vel.dir_vel = -left_right * 1.;
 			if vel.dir_vel.abs() > 1.
 			{
 				vel.dir_vel /= vel.dir_vel.abs();
 			}
-			let max_vert_speed = 3.;
-			let desired_vel = up_down * max_vert_speed;
-			let f = utils::clamp(water_col.water_amount as f32 / 50., 0., 1.);
-			let accel = f * 1. + (1. - f) * 5.;
-			if vel.vel.z > desired_vel
+			steer_vertical(vel, up_down, water_col.water_amount, dt);
+			pilot_states.push((i, pos.pos, vel.vel, vel.dir_vel));
+
+			if get_action_state(controls::Action::DropWater) > 0.5
+				&& time > water_col.time_to_drop
+				&& water_col.water_amount > 0
+			{
+				water_col.time_to_drop = time + 0.4;
+				water_col.water_amount -= 1;
+				state.sfx.play_sound("data/water_drop.ogg")?;
+				spawn_water.push((
+					i,
+					pos.pos + Vector3::new(0., 0., -1.),
+					vel.vel
+						+ Vector3::new(
+							self.rng.gen_range(-0.1..0.1),
+							self.rng.gen_range(-0.1..0.1),
+							0.,
+						),
+				));
+			}
+		}
+		for (i, pos, vel) in spawn_water
+		{
+			let blob = spawn_water_blob(pos, vel, time, &mut self.world);
+			self.world.insert_one(blob, comps::DroppedBy { pilot: i })?;
+			self.pilots[i].num_blobs += 1;
+		}
+
+		// Spatial index.
+		self.entity_grid.clear();
+		for (id, (pos, _)) in self
+			.world
+			.query_mut::<(&comps::Position, &comps::Obelisk)>()
+		{
+			self.entity_grid.push(pos.pos.xy(), (id, GridKind::Obelisk));
+		}
+		for (id, (pos, mushroom)) in self
+			.world
+			.query_mut::<(&comps::Position, &comps::Mushroom)>()
+		{
+			if mushroom.on_fire
+			{
+				self.entity_grid.push(pos.pos.xy(), (id, GridKind::Fire));
+			}
+		}
+		for (id, (pos, _)) in self
+			.world
+			.query_mut::<(&comps::Position, &comps::WaterCollector)>()
+		{
+			self.entity_grid.push(pos.pos.xy(), (id, GridKind::Plane));
+		}
+
+		// Wingmen.
+		let mut spawn_water = vec![];
+		for (id, (pos, vel, water_col, wingman)) in self.world.query_mut::<(
+			&comps::Position,
+			&mut comps::Velocity,
+			&mut comps::WaterCollector,
+			&mut comps::Wingman,
+		)>()
+		{
+			if water_col.water_amount == 0
+			{
+				wingman.refilling = true;
+			}
+			else if water_col.water_amount >= 50
+			{
+				wingman.refilling = false;
+			}
+			let goal = if wingman.refilling
+			{
+				self.lakes.iter().map(|p| p.xy()).min_by(|a, b| {
+					let da = (a - pos.pos.xy()).norm_squared();
+					let db = (b - pos.pos.xy()).norm_squared();
+					da.partial_cmp(&db).unwrap()
+				})
+			}
+			else
+			{
+				self.entity_grid
+					.query_nearest(pos.pos.xy(), 2. * self.size as f32, |&(_, kind)| {
+						kind == GridKind::Fire
+					})
+					.map(|(fire_pos, _)| fire_pos)
+			}
+			.unwrap_or(Point2::new(self.size as f32 / 2., self.size as f32 / 2.));
+
+			let to_goal = goal - pos.pos.xy();
+			// Keep clear of the other planes.
+			let mut steer = to_goal.try_normalize(1e-3).unwrap_or(Vector2::zeros());
+			let separation = 1.5;
+			for (other_pos, (other, kind)) in
+				self.entity_grid.query_radius(pos.pos.xy(), separation)
+			{
+				let away = pos.pos.xy() - other_pos;
+				let dist = away.norm();
+				if kind == GridKind::Plane && other != id && dist > 1e-3
+				{
+					steer += 2. * (1. - dist / separation) * away / dist;
+				}
+			}
+			let dir = Rotation2::new(pos.dir) * Vector2::new(1., 0.);
+			let angle = dir.perp(&steer).atan2(dir.dot(&steer));
+			vel.dir_vel = utils::clamp(2. * angle, -1., 1.);
+
+			let ground = get_height(&self.heightmap, pos.pos.xy()).unwrap_or(0.);
+			let goal_ground = get_height(&self.heightmap, goal).unwrap_or(0.);
+			let near_goal = to_goal.norm() < 2.;
+			let target_z = if wingman.refilling && near_goal
 			{
-				vel.vel.z -= accel * utils::DT;
+				ground + 1.
 			}
-			else if vel.vel.z < desired_vel
+			else if near_goal
 			{
-				vel.vel.z += accel * utils::DT;
+				goal_ground + 5.
 			}
-			let z_speed = vel.vel.z.abs();
-			if z_speed > max_vert_speed
+			else
+			{
+				utils::max(ground, goal_ground) + 6.
+			};
+			let up_down = if predict_collision(
+				&self.heightmap,
+				&self.mushrooms,
+				pos.pos,
+				vel.vel,
+				vel.dir_vel,
+				WINGMAN_LOOK_AHEAD,
+			)
+			.is_some()
 			{
-				vel.vel.z = max_vert_speed.copysign(vel.vel.z);
+				1.
 			}
+			else
+			{
+				utils::clamp(target_z - pos.pos.z, -1., 1.)
+			};
+			steer_vertical(vel, up_down, water_col.water_amount, dt);
 
-			if get_action_state(state, controls::Action::DropWater) > 0.5
+			if !wingman.refilling && time > water_col.time_to_drop && water_col.water_amount > 0
 			{
-				if state.time() > water_col.time_to_drop && water_col.water_amount > 0
+				// Lead the target by how long the water takes to fall.
+				let fall_time = (2. * utils::max(pos.pos.z - goal_ground, 0.) / 5.).sqrt();
+				let landing = pos.pos.xy() + fall_time * vel.vel.xy();
+				if (landing - goal).norm() < 0.75
 				{
-					water_col.time_to_drop = state.time() + 0.4;
+					water_col.time_to_drop = time + 0.4;
 					water_col.water_amount -= 1;
-					state.sfx.play_sound("data/water_drop.ogg")?;
-					spawn_water = Some((
-						pos.pos + Vector3::new(0., 0., -1.),
-						vel.vel
-							+ Vector3::new(rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1), 0.),
-					));
+					spawn_water.push((pos.pos + Vector3::new(0., 0., -1.), vel.vel));
 				}
 			}
 		}
-		if let Some((pos, vel)) = spawn_water
+		for (pos, vel) in spawn_water
 		{
-			spawn_water_blob(pos, vel, state.time(), &mut self.world);
-			self.num_blobs += 1;
+			spawn_water_blob(pos, vel, time, &mut self.world);
 		}
 
 		// Camera.
-		if let Ok(pos) = self.world.get::<&comps::Position>(self.player)
+		for pilot in &mut self.pilots
 		{
-			self.camera_pos = pos.pos;
+			if let Ok((pos, vel)) = self
+				.world
+				.query_one_mut::<(&comps::Position, &comps::Velocity)>(pilot.entity)
+			{
+				pilot.camera.update(
+					pos.pos,
+					vel.vel,
+					state.options.camera_smoothing,
+					state.options.camera_look_ahead,
+					dt,
+				);
+			}
 		}
 
 		// Fixed engine.
-		for (_, (pos, eng, vel)) in
-			self.world
-				.query_mut::<(&comps::Position, &comps::FixedEngine, &mut comps::Velocity)>()
+		for (_, (pos, eng, vel, fuel)) in self.world.query_mut::<(
+			&comps::Position,
+			&comps::FixedEngine,
+			&mut comps::Velocity,
+			Option<&mut comps::Fuel>,
+		)>()
 		{
 			let dir_vel = Rotation2::new(pos.dir) * Vector2::new(1., 0.);
 			// Thinner air -> faster speed.
 			let f = utils::clamp(pos.pos.z / 20., 0., 1.);
 			let height_adj = f * 1.5 + (1. - f) * 1.;
 
-			let horiz_vel = height_adj * eng.power * Vector2::new(dir_vel.x, dir_vel.y);
+			let mut power = eng.power;
+			if let Some(fuel) = fuel
+			{
+				let burn = 0.5 * height_adj * eng.power + 2. * utils::max(vel.vel.z, 0.);
+				fuel.fuel = utils::max(0., fuel.fuel - burn * dt);
+				if fuel.fuel <= 0.
+				{
+					power *= 0.75;
+				}
+			}
+
+			let horiz_vel = height_adj * power * Vector2::new(dir_vel.x, dir_vel.y);
 			vel.vel.x = horiz_vel.x;
 			vel.vel.y = horiz_vel.y;
 		}
 
 		// Collision.
-		let mushroom_height = 2.;
-		for (id, (pos, explode)) in self
+		let airstrips: Vec<_> = self
 			.world
-			.query_mut::<(&comps::Position, &comps::ExplodeOnCollision)>()
+			.query::<(&comps::Position, &comps::Airstrip)>()
+			.iter()
+			.map(|(_, (pos, airstrip))| (pos.pos, airstrip.half_extents))
+			.collect();
+		let mushroom_height = 2.;
+		let mut damage = vec![];
+		for (id, (pos, explode, health, vel, fuel)) in self.world.query_mut::<(
+			&mut comps::Position,
+			&comps::ExplodeOnCollision,
+			Option<&comps::Health>,
+			Option<&mut comps::Velocity>,
+			Option<&comps::Fuel>,
+		)>()
 		{
 			let mut do_explode = false;
 			let mushroom_height = get_mushroom(&self.mushrooms, pos.pos.xy())
-				.and_then(|_| Some(mushroom_height))
+				.map(|_| mushroom_height)
 				.unwrap_or(0.);
+			let on_airstrip = airstrips.iter().any(|(strip_pos, half_extents)| {
+				let diff = pos.pos - strip_pos;
+				diff.x.abs() < half_extents.x && diff.y.abs() < half_extents.y
+			});
 			if let Some(h) = get_height(&self.heightmap, pos.pos.xy())
 			{
-				let h = h + mushroom_height;
 				if pos.pos.z - h < 0.5
 				{
+					match vel
+					{
+						// Planes touch down on airstrips, unless they come in too steep.
+						Some(vel)
+							if on_airstrip && fuel.is_some() && vel.vel.z > -LANDING_SPEED =>
+						{
+							pos.pos.z = h + 0.5;
+							vel.vel.z = utils::max(vel.vel.z, 0.);
+						}
+						_ => do_explode = true,
+					}
+				}
+				else if pos.pos.z - h < mushroom_height
+				{
+					// Flying into the mushroom itself.
 					do_explode = true;
 				}
+				else if pos.pos.z - (h + mushroom_height) < 0.5
+				{
+					// Grazing a mushroom only hurts things that can take damage.
+					if health.is_some()
+					{
+						damage.push((id, 40. * dt));
+					}
+					else
+					{
+						do_explode = true;
+					}
+				}
 			}
 			else
 			{
@@ -1012,32 +2087,86 @@ impl Map
 			}
 		}
 
-		// Collision alert.
-		self.collision_alert = false;
-		if let (Some(player_pos), Some(player_vel)) = (player_pos, player_vel)
+		// Smoke.
+		for (id, (pos, _)) in self
+			.world
+			.query::<(&comps::Position, &comps::Health)>()
+			.iter()
 		{
-			let mut alert = false;
-			for dt in [0.2, 0.4, 0.6, 0.8, 1.]
+			if let (Some(mushroom), Some(h)) = (
+				get_mushroom(&self.mushrooms, pos.pos.xy()),
+				get_height(&self.heightmap, pos.pos.xy()),
+			)
 			{
-				let test_pos = player_pos + dt * player_vel;
+				let on_fire = self
+					.world
+					.get::<&comps::Mushroom>(mushroom)
+					.map(|m| m.on_fire)
+					.unwrap_or(false);
+				if on_fire && pos.pos.z - h < mushroom_height + 5.
+				{
+					damage.push((id, 10. * dt));
+				}
+			}
+		}
 
-				let mushroom_height = get_mushroom(&self.mushrooms, test_pos.xy())
-					.and_then(|_| Some(mushroom_height))
-					.unwrap_or(0.);
-				if let Some(h) = get_height(&self.heightmap, test_pos.xy())
+		// Damage.
+		for (id, amount) in damage
+		{
+			if let Ok(mut health) = self.world.get::<&mut comps::Health>(id)
+			{
+				health.health -= amount;
+				if health.health <= 0.
 				{
-					let h = h + mushroom_height;
-					if test_pos.z - h < 0.5
-					{
-						alert = true;
-					}
+					health.health = 0.;
+					to_die.push(id);
 				}
 			}
-			self.collision_alert = alert;
-			if self.collision_alert && state.time() > self.time_to_play_alert
+		}
+
+		// Damage vignette, for the worst off local pilot.
+		let mut damage: f32 = 0.;
+		for &view in &self.views
+		{
+			if let Ok(health) = self.world.get::<&comps::Health>(self.pilots[view].entity)
+			{
+				damage = damage.max(1. - health.health / health.max_health);
+			}
+		}
+		state.shader_uniforms.insert("damage".into(), damage);
+
+		// Collision alert.
+		for pilot in &mut self.pilots
+		{
+			pilot.collision_alert = false;
+		}
+		for &(i, pilot_pos, pilot_vel, pilot_dir_vel) in &pilot_states
+		{
+			let pilot = &mut self.pilots[i];
+			let time_to_impact = predict_collision(
+				&self.heightmap,
+				&self.mushrooms,
+				pilot_pos,
+				pilot_vel,
+				pilot_dir_vel,
+				state.options.collision_look_ahead,
+			);
+			pilot.collision_alert = time_to_impact.is_some();
+			if let Some(time_to_impact) = time_to_impact
 			{
-				state.sfx.play_sound("data/alert.ogg")?;
-				self.time_to_play_alert = state.time() + 3.;
+				// The alert repeats faster the closer the impact is.
+				let interval =
+					3. * time_to_impact / utils::max(state.options.collision_look_ahead, 1e-3);
+				let next_alert = time + utils::clamp(interval, 0.5, 3.) as f64;
+				if time > pilot.time_to_play_alert
+				{
+					state.sfx.play_sound("data/alert.ogg")?;
+					pilot.time_to_play_alert = next_alert;
+				}
+				else
+				{
+					pilot.time_to_play_alert = pilot.time_to_play_alert.min(next_alert);
+				}
 			}
 		}
 
@@ -1055,11 +2184,21 @@ impl Map
 			.world
 			.query_mut::<(&mut comps::Velocity, &comps::AffectedByGravity)>()
 		{
-			vel.vel.z -= utils::DT * 5.;
+			vel.vel.z -= dt * 5.;
 		}
-
-		// Friction.
-		for (_, (vel, _)) in self
+		for (_, (vel, particle)) in self
+			.world
+			.query_mut::<(&mut comps::Velocity, &comps::Particle)>()
+		{
+			if let Some(emitter) = state.get_emitter(&particle.emitter)
+			{
+				vel.vel.z -= dt * emitter.gravity;
+				vel.vel *= utils::max(0., 1. - dt * emitter.drag);
+			}
+		}
+
+		// Friction.
+		for (_, (vel, _)) in self
 			.world
 			.query_mut::<(&mut comps::Velocity, &comps::AffectedByGravity)>()
 		{
@@ -1068,45 +2207,55 @@ impl Map
 			{
 				let friction = vel.vel.xy().normalize();
 				let friction = 0.5 * friction * vel.vel.xy().norm_squared();
-				vel.vel.x -= utils::DT * friction.x;
-				vel.vel.y -= utils::DT * friction.y;
+				vel.vel.x -= dt * friction.x;
+				vel.vel.y -= dt * friction.y;
 			}
 		}
 
 		// Obelisk.
-		let mut teleport = None;
+		let mut teleport = vec![];
 		let mut near_obelisk = false;
-		if let Some(player_pos) = player_pos
+		let effect_dist = 3.;
+		for &(i, pilot_pos, _, _) in &pilot_states
 		{
-			for (_, (pos, obelisk)) in self
-				.world
-				.query_mut::<(&comps::Position, &comps::Obelisk)>()
+			for (pos, (id, kind)) in self.entity_grid.query_radius(pilot_pos.xy(), effect_dist)
 			{
-				let norm = (player_pos.xy() - pos.pos.xy()).norm();
+				if kind != GridKind::Obelisk
+				{
+					continue;
+				}
+				let obelisk = match self.world.get::<&comps::Obelisk>(id)
+				{
+					Ok(obelisk) => obelisk,
+					Err(_) => continue,
+				};
+				let norm = (pilot_pos.xy() - pos).norm();
 
-				let effect_dist = 3.;
 				if norm < effect_dist
 				{
 					let f = norm / effect_dist;
-					state.swirl_amount = 0. * f + 5. * (1. - f);
+					state.swirl_amount = utils::max(
+						if near_obelisk { state.swirl_amount } else { 0. },
+						0. * f + 5. * (1. - f),
+					);
 					near_obelisk = true;
 				}
 				if norm < 1.
 				{
-					teleport = Some(obelisk.dest);
+					teleport.push((self.pilots[i].entity, obelisk.dest));
 				}
 			}
 		}
 		if !near_obelisk
 		{
-			state.swirl_amount = utils::max(state.swirl_amount - 12. * utils::DT, 0.);
+			state.swirl_amount = utils::max(state.swirl_amount - 12. * dt, 0.);
 		}
 		self.obelisk_sound
 			.set_gain(state.swirl_amount / 5.)
 			.unwrap();
-		if let Some(dest) = teleport
+		for (entity, dest) in teleport
 		{
-			if let Ok(mut pos) = self.world.get::<&mut comps::Position>(self.player)
+			if let Ok(mut pos) = self.world.get::<&mut comps::Position>(entity)
 			{
 				state.sfx.play_sound("data/teleport.ogg")?;
 				pos.pos = dest;
@@ -1118,9 +2267,9 @@ impl Map
 			.world
 			.query_mut::<(&mut comps::Position, &comps::Velocity)>()
 		{
-			pos.pos += utils::DT * vel.vel;
+			pos.pos += dt * vel.vel;
 			pos.pos.z = utils::clamp(pos.pos.z, 0., 15.);
-			pos.dir += utils::DT * vel.dir_vel;
+			pos.dir += dt * vel.dir_vel;
 		}
 
 		// Water collection.
@@ -1131,9 +2280,9 @@ impl Map
 		{
 			if let Some(h) = get_height(&self.heightmap, pos.pos.xy())
 			{
-				if h < 0.1 && pos.pos.z - h < 2. && state.time() > water_col.time_to_splash
+				if h < 0.1 && pos.pos.z - h < 2. && time > water_col.time_to_splash
 				{
-					water_col.time_to_splash = state.time() + 0.25;
+					water_col.time_to_splash = time + 0.25;
 					water_col.water_amount += 5;
 
 					if water_col.water_amount < 100
@@ -1143,8 +2292,8 @@ impl Map
 						{
 							state.sfx.play_positional_sound(
 								"data/water_splash.ogg",
-								world_to_screen(pos.pos),
-								world_to_screen(player_pos),
+								world_to_screen(pos.pos, player_rotation),
+								world_to_screen(player_pos, player_rotation),
 								1.,
 							)?;
 						}
@@ -1155,43 +2304,80 @@ impl Map
 		}
 		for pos in add_splash
 		{
-			spawn_splash(pos, state.time(), &mut self.world);
+			spawn_splash(pos, time, &mut self.world);
+		}
+
+		// Refuelling.
+		for (_, (pos, fuel)) in self
+			.world
+			.query_mut::<(&comps::Position, &mut comps::Fuel)>()
+		{
+			for (strip_pos, half_extents) in &airstrips
+			{
+				let diff = pos.pos - strip_pos;
+				if diff.x.abs() < half_extents.x && diff.y.abs() < half_extents.y && diff.z < 2.
+				{
+					fuel.fuel = utils::min(fuel.max_fuel, fuel.fuel + 25. * dt);
+				}
+			}
 		}
 
 		// Particle spawners.
 		let mut to_spawn = vec![];
-		for (_, (pos, spawners)) in self
-			.world
-			.query_mut::<(&comps::Position, &mut comps::ParticleSpawners)>()
+		for (_, (pos, spawners, health)) in self.world.query_mut::<(
+			&comps::Position,
+			&mut comps::ParticleSpawners,
+			Option<&comps::Health>,
+		)>()
 		{
-			for mut spawner in &mut spawners.spawners
+			let damage = health.map(|h| 1. - h.health / h.max_health).unwrap_or(0.);
+			let smoking = damage > 0.5;
+			for spawner in &mut spawners.spawners
 			{
-				if state.time() > spawner.time_to_spawn
+				let mut name = &spawner.emitter;
+				let mut emitter = match state.get_emitter(name)
+				{
+					Some(emitter) => emitter,
+					None => continue,
+				};
+				if let (true, Some(smoke)) = (smoking, &emitter.smoke)
+				{
+					if let Some(smoke_emitter) = state.get_emitter(smoke)
+					{
+						name = smoke;
+						emitter = smoke_emitter;
+					}
+				}
+				let mut count = 0;
+				if !spawner.started
 				{
-					let offset_xy = Rotation2::new(pos.dir) * spawner.offset.xy();
-					let offset = Vector3::new(offset_xy.x, offset_xy.y, spawner.offset.z);
+					spawner.started = true;
+					count += emitter.burst;
+				}
+				if emitter.rate > 0. && time > spawner.time_to_spawn
+				{
+					count += 1;
+					spawner.time_to_spawn = time + 1. / emitter.rate as f64;
+				}
 
-					let vel = match spawner.kind
+				let offset_xy = Rotation2::new(pos.dir) * spawner.offset.xy();
+				let offset = Vector3::new(offset_xy.x, offset_xy.y, spawner.offset.z);
+				for _ in 0..count
+				{
+					let mut vel = emitter.get_vel(&mut rng);
+					if smoking
 					{
-						comps::ParticleKind::Stationary => Vector3::zeros(),
-						comps::ParticleKind::Fire =>
-						{
-							Vector3::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5), 5.)
-						}
-					};
-					to_spawn.push((
-						pos.pos + offset,
-						vel,
-						spawner.sprite.clone(),
-						spawner.duration,
-					));
-					spawner.time_to_spawn = state.time() + spawner.spawn_delay;
+						// The worse the damage, the faster the smoke rises.
+						vel.z += damage;
+					}
+					to_spawn.push((pos.pos + offset, vel, name.clone()));
 				}
 			}
 		}
-		for (pos, vel, sprite, duration) in to_spawn
+		for (pos, vel, name) in to_spawn
 		{
-			spawn_particle(pos, vel, sprite, state.time(), duration, &mut self.world);
+			let emitter = state.get_emitter(&name).unwrap();
+			spawn_particle(pos, vel, &name, emitter, time, &mut self.world)?;
 		}
 
 		// Fire counting.
@@ -1213,15 +2399,15 @@ impl Map
 
 		// Fire spread
 		let mut ignite = vec![];
-		if state.time() > self.time_to_spread_fire
+		if time > self.time_to_spread_fire
 		{
 			for (_, (pos, mushroom)) in self
 				.world
 				.query_mut::<(&comps::Position, &comps::Mushroom)>()
 			{
-				if mushroom.on_fire && rng.gen_bool(state.options.fire_spread_probability as f64)
+				if mushroom.on_fire && self.rng.gen_bool(self.fire_spread_probability as f64)
 				{
-					let idx = rng.gen_range(0..4);
+					let idx = self.rng.gen_range(0..4);
 					let [dx, dy] = [[-1., 0.], [1., 0.], [0., 1.], [0., -1.]][idx];
 					if let Some(mushroom) =
 						get_mushroom(&self.mushrooms, pos.pos.xy() + Vector2::new(dx, dy))
@@ -1230,7 +2416,7 @@ impl Map
 					}
 				}
 			}
-			self.time_to_spread_fire = state.time() + 15.;
+			self.time_to_spread_fire = time + 15.;
 		}
 		for mushroom in ignite
 		{
@@ -1240,7 +2426,7 @@ impl Map
 		// Time to die
 		for (id, time_to_die) in self.world.query_mut::<&comps::TimeToDie>()
 		{
-			if state.time() > time_to_die.time_to_die
+			if time > time_to_die.time_to_die
 			{
 				to_die.push(id);
 			}
@@ -1251,9 +2437,11 @@ impl Map
 		let mut extinguish = vec![];
 		for id in &to_die
 		{
-			if let Ok((pos, on_death_effects)) = self
-				.world
-				.query_one_mut::<(&comps::Position, &comps::OnDeathEffects)>(*id)
+			if let Ok((pos, on_death_effects, dropped_by)) = self.world.query_one_mut::<(
+				&comps::Position,
+				&comps::OnDeathEffects,
+				Option<&comps::DroppedBy>,
+			)>(*id)
 			{
 				for effect in &on_death_effects.effects
 				{
@@ -1267,7 +2455,7 @@ impl Map
 						{
 							if let Some(mushroom) = get_mushroom(&self.mushrooms, pos.pos.xy())
 							{
-								extinguish.push((pos.pos, mushroom));
+								extinguish.push((pos.pos, mushroom, dropped_by.map(|d| d.pilot)));
 							}
 						}
 					}
@@ -1286,12 +2474,12 @@ impl Map
 					{
 						state.sfx.play_positional_sound(
 							"data/explosion.ogg",
-							world_to_screen(pos),
-							world_to_screen(player_pos),
+							world_to_screen(pos, player_rotation),
+							world_to_screen(player_pos, player_rotation),
 							1.,
 						)?;
 					}
-					spawn_explosion(pos, state.time(), &mut self.world);
+					spawn_explosion(pos, time, &mut self.world);
 				}
 				comps::ExplosionKind::Splash =>
 				{
@@ -1299,28 +2487,31 @@ impl Map
 					{
 						state.sfx.play_positional_sound(
 							"data/water_splash.ogg",
-							world_to_screen(pos),
-							world_to_screen(player_pos),
+							world_to_screen(pos, player_rotation),
+							world_to_screen(player_pos, player_rotation),
 							1.,
 						)?;
 					}
-					spawn_splash(pos, state.time(), &mut self.world);
+					spawn_splash(pos, time, &mut self.world);
 				}
 			}
 		}
 
 		// Extinguish
-		for (pos, mushroom) in extinguish
+		for (pos, mushroom, pilot) in extinguish
 		{
 			if change_on_fire(mushroom, false, &mut self.world)?
 			{
-				self.num_extinguished += 1;
+				if let Some(pilot) = pilot
+				{
+					self.pilots[pilot].num_extinguished += 1;
+				}
 				if let Some(player_pos) = player_pos
 				{
 					state.sfx.play_positional_sound(
 						"data/extinguish.ogg",
-						world_to_screen(pos),
-						world_to_screen(player_pos),
+						world_to_screen(pos, player_rotation),
+						world_to_screen(player_pos, player_rotation),
 						1.,
 					)?;
 				}
@@ -1339,11 +2530,37 @@ impl Map
 		Ok(None)
 	}
 
+	/// Lays the views out for a new buffer size. This happens from the options
+	/// menu, so that is where the player is left.
+	pub fn resize(&mut self, state: &game_state::GameState, display_width: f32, display_height: f32)
+	{
+		self.display_width = display_width;
+		self.display_height = display_height;
+		if !self.subscreens.is_empty()
+		{
+			self.subscreens = vec![
+				ui::SubScreen::InGameMenu(ui::InGameMenu::new(
+					display_width,
+					display_height,
+					self.seed,
+				)),
+				ui::SubScreen::OptionsMenu(ui::OptionsMenu::new(
+					state,
+					display_width,
+					display_height,
+				)),
+			];
+		}
+	}
+
 	pub fn input(
 		&mut self, event: &Event, state: &mut game_state::GameState,
 	) -> Result<Option<game_state::NextScreen>>
 	{
-		state.controls.decode_event(event);
+		for i in 0..self.views.len()
+		{
+			state.get_player_controls_mut(i).decode_event(event);
+		}
 		if self.ui_state == UIState::InMenu
 		{
 			if let Some(action) = self
@@ -1378,103 +2595,478 @@ impl Map
 		}
 		else
 		{
-			match event
+			for (i, &pilot) in self.views.iter().enumerate()
 			{
-				Event::KeyDown { keycode, .. } => match keycode
+				for action in state.get_player_controls(i).get_event_actions()
 				{
-					KeyCode::Escape =>
+					match action
 					{
-						state.sfx.play_sound("data/ui2.ogg").unwrap();
-						self.subscreens
-							.push(ui::SubScreen::InGameMenu(ui::InGameMenu::new(
-								self.display_width,
-								self.display_height,
-								self.seed,
-							)));
-						self.ui_state = UIState::InMenu;
-						state.paused = true;
-						state.hide_mouse = false;
-						state.swirl_amount = 0.;
+						controls::Action::ZoomIn => self.pilots[pilot].camera.zoom_by(1.25),
+						controls::Action::ZoomOut => self.pilots[pilot].camera.zoom_by(0.8),
+						controls::Action::RotateLeft => self.pilots[pilot].camera.rotate(-1),
+						controls::Action::RotateRight => self.pilots[pilot].camera.rotate(1),
+						_ => (),
 					}
-					_ => (),
-				},
-				_ => (),
+				}
+			}
+			let pause = (0..self.views.len()).any(|i| {
+				state
+					.get_player_controls(i)
+					.get_event_actions()
+					.contains(&controls::Action::Pause)
+			});
+			if pause
+			{
+				state.sfx.play_sound("data/ui2.ogg").unwrap();
+				self.subscreens
+					.push(ui::SubScreen::InGameMenu(ui::InGameMenu::new(
+						self.display_width,
+						self.display_height,
+						self.seed,
+					)));
+				self.ui_state = UIState::InMenu;
+				// Network games go on without this pilot.
+				state.paused = state.net.is_none();
+				state.hide_mouse = false;
+				state.swirl_amount = 0.;
 			}
 		}
 		Ok(None)
 	}
 
-	pub fn draw(&mut self, state: &game_state::GameState) -> Result<()>
+	/// Where the terrain tile of a cell is drawn (before shifting by the
+	/// camera), and its variant.
+	fn get_tile(&self, x: i32, y: i32, rotation: i32) -> (Point2<f32>, i32)
 	{
-		state.core.clear_to_color(Color::from_rgb_f(0., 0., 0.));
+		let corners = TILE_CORNERS[rotation as usize];
+		let mut vals = [0; 4];
+		for (val, &(sx, sy)) in vals.iter_mut().zip(corners.iter())
+		{
+			*val = self.heightmap[((x + sx) + (y + sy) * self.size) as usize];
+		}
+		let min_val = *vals.iter().min().unwrap();
+		for v in &mut vals
+		{
+			*v -= min_val;
+		}
 
-		let camera_xy = world_to_screen(self.camera_pos);
+		let variant = decode_tile(vals, x, y, min_val);
+		(tile_position(x, y, min_val, rotation), variant)
+	}
 
-		// Map drawing
-		state.core.hold_bitmap_drawing(true);
-		let dx = self.display_width / 2. - camera_xy.x;
-		let dy = self.display_height / 2. - camera_xy.y;
+	/// The cells of a terrain chunk, in drawing order.
+	fn get_chunk_cells(&self, (rotation, chunk_u, chunk_v): ChunkKey) -> Vec<(i32, i32)>
+	{
+		let (top_x, top_y) = TILE_CORNERS[rotation as usize][0];
+		let mut cells = vec![];
+		// Rows are drawn back to front.
+		for row in 0..2 * CHUNK_SIZE - 1
+		{
+			for u in 0..CHUNK_SIZE
+			{
+				let v = row - u;
+				if !(0..CHUNK_SIZE).contains(&v)
+				{
+					continue;
+				}
+				// The top corner is half a unit back from (u, v) in the rotated
+				// view.
+				let (top_corner_x, top_corner_y) = rotate_xy(
+					(chunk_u * CHUNK_SIZE + u) as f32 - 0.5,
+					(chunk_v * CHUNK_SIZE + v) as f32 - 0.5,
+					-rotation,
+				);
+				let x = (top_corner_x + 0.5).round() as i32 - top_x;
+				let y = (top_corner_y + 0.5).round() as i32 - top_y;
+				if x >= 0 && y >= 0 && x < self.size - 1 && y < self.size - 1
+				{
+					cells.push((x, y));
+				}
+			}
+		}
+		cells
+	}
+
+	fn get_chunk_bounds(
+		&self, key: ChunkKey, state: &game_state::GameState,
+	) -> Option<(Point2<f32>, Point2<f32>)>
+	{
+		if let Some(chunk) = self.terrain_chunks.get(&key)
+		{
+			return Some(chunk.bounds);
+		}
 		let tiles = state.get_sprite("data/terrain.cfg").unwrap();
-		for y in 0..self.size - 1
+		let mut bounds: Option<(Point2<f32>, Point2<f32>)> = None;
+		for (x, y) in self.get_chunk_cells(key)
 		{
-			for x in 0..self.size - 1
+			let (min, max) = tiles.get_bounds(self.get_tile(x, y, key.0).0);
+			bounds = Some(match bounds
 			{
-				let mut min_val = 1000;
-				let mut vals = [0; 4];
-				let mut idx = 0;
-				for sy in [0, 1]
+				Some((old_min, old_max)) => (
+					Point2::new(old_min.x.min(min.x), old_min.y.min(min.y)),
+					Point2::new(old_max.x.max(max.x), old_max.y.max(max.y)),
+				),
+				None => (min, max),
+			});
+		}
+		bounds
+	}
+
+	/// The terrain chunks that reach into a view, in drawing order.
+	fn get_visible_chunks(
+		&self, camera: &camera::Camera, view_width: f32, view_height: f32,
+		state: &game_state::GameState,
+	) -> Vec<ChunkKey>
+	{
+		let rotation = camera.rotation;
+		let (view_min, view_max) = view_bounds(camera, view_width, view_height);
+		// In the rotated view, the cell with its top corner at (u, v) is drawn
+		// `u - v` half tiles across and `u + v` half tiles down, lifted by its
+		// height.
+		let max_height = *self.heightmap.iter().max().unwrap_or(&0) as f32;
+		let min_row = ((view_min.y - 32.) / 32.).floor() as i32;
+		let max_row = ((view_max.y + 96. + 24. * max_height) / 32.).ceil() as i32;
+		let min_col = (view_min.x / 64.).floor() as i32 - 1;
+		let max_col = (view_max.x / 64.).ceil() as i32 + 1;
+		let min_u = (min_row + min_col).div_euclid(2).div_euclid(CHUNK_SIZE);
+		let max_u = (max_row + max_col).div_euclid(2).div_euclid(CHUNK_SIZE);
+		let min_v = (min_row - max_col).div_euclid(2).div_euclid(CHUNK_SIZE);
+		let max_v = (max_row - min_col).div_euclid(2).div_euclid(CHUNK_SIZE);
+
+		let mut chunks = vec![];
+		for chunk_u in min_u..=max_u
+		{
+			for chunk_v in min_v..=max_v
+			{
+				let key = (rotation, chunk_u, chunk_v);
+				if let Some(bounds) = self.get_chunk_bounds(key, state)
 				{
-					for sx in [0, 1]
+					if is_in_view(bounds, view_min, view_max)
 					{
-						let z = self.heightmap[((x + sx) + (y + sy) * self.size) as usize];
-						min_val = utils::min(min_val, z);
-						vals[idx] = z;
-						idx += 1;
+						chunks.push(key);
 					}
 				}
-				for v in &mut vals
+			}
+		}
+		// Back to front.
+		chunks.sort_by_key(|&(_, u, v)| (u + v, u));
+		chunks
+	}
+
+	fn build_chunk(&self, key: ChunkKey, state: &game_state::GameState) -> Result<TerrainChunk>
+	{
+		let rotation = key.0;
+		let tiles = state.get_sprite("data/terrain.cfg").unwrap();
+		let cells = self.get_chunk_cells(key);
+		let (min, max) = self.get_chunk_bounds(key, state).unwrap();
+		let bitmap = Bitmap::new(
+			&state.core,
+			(max.x - min.x).ceil() as i32,
+			(max.y - min.y).ceil() as i32,
+		)
+		.map_err(|_| "Couldn't create terrain chunk bitmap".to_string())?;
+
+		state.core.set_target_bitmap(Some(&bitmap));
+		state
+			.core
+			.clear_to_color(Color::from_rgba_f(0., 0., 0., 0.));
+		state.core.hold_bitmap_drawing(true);
+		let mut occluders = vec![];
+		for &(x, y) in &cells
+		{
+			let (xy, variant) = self.get_tile(x, y, rotation);
+			tiles.draw(
+				utils::round_point(xy - min.coords),
+				variant,
+				Color::from_rgb_f(1., 1., 1.),
+				state,
+			);
+			if self.is_occluding_tile(x, y, rotation)
+			{
+				occluders.push((x, y));
+			}
+		}
+		state.core.hold_bitmap_drawing(false);
+
+		Ok(TerrainChunk {
+			bitmap,
+			bounds: (min, max),
+			num_tiles: cells.len() as i32,
+			occluders,
+			last_used: 0,
+		})
+	}
+
+	/// Draws the terrain chunks the views are about to show. This changes the
+	/// target bitmap, so call it before `draw`.
+	pub fn update_terrain_chunks(&mut self, state: &game_state::GameState) -> Result<()>
+	{
+		self.num_draws += 1;
+		let view_width = self.display_width / self.views.len() as f32;
+		for &pilot in &self.views
+		{
+			let camera = self.pilots[pilot].camera.interpolate(state.interpolation);
+			for key in self.get_visible_chunks(&camera, view_width, self.display_height, state)
+			{
+				if !self.terrain_chunks.contains_key(&key)
 				{
-					*v -= min_val;
+					let chunk = self.build_chunk(key, state)?;
+					self.terrain_chunks.insert(key, chunk);
 				}
+				self.terrain_chunks.get_mut(&key).unwrap().last_used = self.num_draws;
+			}
+		}
+		let mut total_bytes: usize = self.terrain_chunks.values().map(|c| c.num_bytes()).sum();
+		while total_bytes > MAX_CHUNK_BYTES
+		{
+			let (&key, chunk) = self
+				.terrain_chunks
+				.iter()
+				.min_by_key(|(_, chunk)| chunk.last_used)
+				.unwrap();
+			if chunk.last_used == self.num_draws
+			{
+				break;
+			}
+			total_bytes -= chunk.num_bytes();
+			self.terrain_chunks.remove(&key);
+		}
+		Ok(())
+	}
 
-				let variant = decode_tile(vals, x, y, min_val);
-				let xy = world_to_screen(Point3::new(x as f32, y as f32, min_val as f32));
-				tiles.draw(
-					utils::round_point(xy - utils::Vec2D::new(64. - dx, 96. - dy)),
-					variant,
-					Color::from_rgb_f(1., 1., 1.),
-					state,
-				);
+	/// Drops all the pre-drawn terrain, e.g. after the terrain sprite changes.
+	pub fn invalidate_terrain(&mut self)
+	{
+		self.terrain_chunks.clear();
+	}
+
+	/// Draws a terrain tile shifted by `offset`, unless it falls outside the
+	/// view (between the `view` corners). Returns whether it was drawn.
+	fn draw_tile(
+		&self, (x, y): (i32, i32), rotation: i32, offset: Vector2<f32>,
+		view: (Point2<f32>, Point2<f32>), tint: Color, state: &game_state::GameState,
+	) -> bool
+	{
+		let tiles = state.get_sprite("data/terrain.cfg").unwrap();
+		let (xy, variant) = self.get_tile(x, y, rotation);
+		if !is_in_view(tiles.get_bounds(xy), view.0, view.1)
+		{
+			return false;
+		}
+		tiles.draw(utils::round_point(xy + offset), variant, tint, state);
+		true
+	}
+
+	/// Whether a tile rises above any of the tiles right behind it in the
+	/// rotated view, so it can hide what is there.
+	fn is_occluding_tile(&self, x: i32, y: i32, rotation: i32) -> bool
+	{
+		let cell_heights = |x: i32, y: i32| {
+			let mut min_val = i32::MAX;
+			let mut max_val = i32::MIN;
+			for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)]
+			{
+				let z = self.heightmap[((x + sx) + (y + sy) * self.size) as usize];
+				min_val = utils::min(min_val, z);
+				max_val = utils::max(max_val, z);
+			}
+			(min_val, max_val)
+		};
+		let (_, max_val) = cell_heights(x, y);
+		for (du, dv) in [(-1., 0.), (0., -1.), (-1., -1.)]
+		{
+			let (bx, by) = rotate_xy(du, dv, -rotation);
+			let (bx, by) = (x + bx as i32, y + by as i32);
+			if bx < 0 || by < 0 || bx >= self.size - 1 || by >= self.size - 1
+			{
+				continue;
+			}
+			if cell_heights(bx, by).0 < max_val
+			{
+				return true;
 			}
 		}
+		false
+	}
+
+	/// Whether the terrain hides `pos` in a view with the given rotation.
+	fn is_occluded(&self, pos: Point3<f32>, rotation: i32) -> bool
+	{
+		// Walk from `pos` towards the viewer, along the line that projects onto
+		// a single screen point.
+		let (dir_x, dir_y) = rotate_xy(1., 1., -rotation);
+		let max_height = *self.heightmap.iter().max().unwrap_or(&0) as f32;
+		let mut t = 0.25;
+		loop
+		{
+			let z = pos.z + t * 64. / 24.;
+			if z > max_height
+			{
+				return false;
+			}
+			match get_height(
+				&self.heightmap,
+				Point2::new(pos.x + t * dir_x, pos.y + t * dir_y),
+			)
+			{
+				Some(h) if h > z => return true,
+				Some(_) => (),
+				None => return false,
+			}
+			t += 0.25;
+		}
+	}
+
+	/// Draws the world centered on `cx`/`cy`, in a view `view_width` by
+	/// `view_height` pixels large before zooming.
+	fn draw_view(
+		&self, camera: &camera::Camera, cx: f32, cy: f32, view_width: f32, view_height: f32,
+		state: &game_state::GameState,
+	) -> DrawStats
+	{
+		let start_time = state.core.get_time();
+		let mut stats = DrawStats::default();
+		let rotation = camera.rotation;
+		let camera_xy = world_to_screen(camera.pos, rotation) + camera.look_offset;
+		let (view_min, view_max) = view_bounds(camera, view_width, view_height);
+
+		// Lighting
+		let daylight = get_daylight(self.get_hour(state));
+		let ambient = get_ambient_light(daylight);
+		let mut fires = vec![];
+		for (_, (pos, mushroom)) in self
+			.world
+			.query::<(&comps::Position, &comps::Mushroom)>()
+			.iter()
+		{
+			if mushroom.on_fire
+			{
+				fires.push(pos.pos + Vector3::new(0., 0., 1.));
+			}
+		}
+
+		// Map drawing
+		let dx = cx - camera_xy.x;
+		let dy = cy - camera_xy.y;
+		stats.tiles_total = (self.size - 1) * (self.size - 1);
+		let mut items = vec![];
+		for key in self.get_visible_chunks(camera, view_width, view_height, state)
+		{
+			// Built by `update_terrain_chunks`.
+			let chunk = match self.terrain_chunks.get(&key)
+			{
+				Some(chunk) => chunk,
+				None => continue,
+			};
+			let (min, _) = chunk.bounds;
+			state.core.draw_tinted_bitmap(
+				&chunk.bitmap,
+				ambient,
+				(min.x + dx).round(),
+				(min.y + dy).round(),
+				Flag::zero(),
+			);
+			stats.tiles_drawn += chunk.num_tiles;
+			for &(x, y) in &chunk.occluders
+			{
+				// Drawn again over anything behind it.
+				let depth = cell_depth(Point2::new(x as f32, y as f32), rotation);
+				items.push(((depth, 0, 0., 0.), ViewItem::Tile { x, y }));
+			}
+		}
+
+		// Airstrips
+		for (_, (pos, airstrip)) in self
+			.world
+			.query::<(&comps::Position, &comps::Airstrip)>()
+			.iter()
+		{
+			let he = airstrip.half_extents;
+			let mut vertices = vec![];
+			for [sx, sy] in [[-1., -1.], [1., -1.], [1., 1.], [-1., 1.]]
+			{
+				let xy =
+					world_to_screen(pos.pos + Vector3::new(sx * he.x, sy * he.y, 0.), rotation);
+				vertices.push((xy.x + dx, xy.y + dy));
+			}
+			state.prim.draw_filled_polygon(
+				&vertices,
+				Color::from_rgba_f(0.3, 0.3, 0.3, 0.8).modulate(ambient),
+			);
+			let axis = if he.x > he.y
+			{
+				Vector3::new(he.x - 0.3, 0., 0.)
+			}
+			else
+			{
+				Vector3::new(0., he.y - 0.3, 0.)
+			};
+			let start = world_to_screen(pos.pos - axis, rotation);
+			let end = world_to_screen(pos.pos + axis, rotation);
+			state.prim.draw_line(
+				start.x + dx,
+				start.y + dy,
+				end.x + dx,
+				end.y + dy,
+				Color::from_rgb_f(0.9, 0.9, 0.9).modulate(ambient),
+				2.,
+			);
+		}
+		state.core.hold_bitmap_drawing(true);
 
 		// Shadows
-		for (_, (pos, shadow)) in self
+		for (_, (pos, prev, shadow)) in self
 			.world
-			.query::<(&comps::Position, &comps::CastsShadow)>()
+			.query::<(
+				&comps::Position,
+				Option<&comps::PrevPosition>,
+				&comps::CastsShadow,
+			)>()
 			.iter()
 		{
+			let pos = &interpolate_position(pos, prev, state.interpolation);
 			if let Some(h) = get_height(&self.heightmap, pos.pos.xy())
 			{
-				let xy = world_to_screen(Point3::new(pos.pos.x, pos.pos.y, h));
-				let xy = utils::round_point(xy + Vector2::new(dx, dy));
-
-				let sprite = "data/shadow.cfg";
-				let sprite = state
-					.get_sprite(&sprite)
-					.expect(&format!("Could not find sprite: {}", sprite));
-				sprite.draw(xy, shadow.size, Color::from_rgb_f(1., 1., 1.), state);
+				let xy = world_to_screen(Point3::new(pos.pos.x, pos.pos.y, h), rotation);
+				let sprite = state.get_sprite("data/shadow.cfg").unwrap();
+				if !is_in_view(sprite.get_bounds(xy), view_min, view_max)
+				{
+					continue;
+				}
+				let depth = cell_depth(pos.pos.xy(), rotation);
+				items.push((
+					(depth, 1, 0., 0.),
+					ViewItem::Shadow {
+						xy,
+						size: shadow.size,
+					},
+				));
 			}
 		}
 
 		// Sprites
-		let mut pos_and_sprite = vec![];
-		for (id, (pos, drawable)) in self
+		let mut silhouettes = vec![];
+		for (id, (pos, prev, drawable)) in self
 			.world
-			.query::<(&comps::Position, &comps::Drawable)>()
+			.query::<(
+				&comps::Position,
+				Option<&comps::PrevPosition>,
+				&comps::Drawable,
+			)>()
 			.iter()
 		{
-			let xy = world_to_screen(pos.pos);
+			let pos = &interpolate_position(pos, prev, state.interpolation);
+			let xy = world_to_screen(pos.pos, rotation);
+			stats.sprites_total += 1;
+			let sprite = drawable.kind.get_sprite();
+			let sprite = state
+				.get_sprite(sprite)
+				.unwrap_or_else(|| panic!("Could not find sprite: {}", sprite));
+			if !is_in_view(sprite.get_bounds(xy), view_min, view_max)
+			{
+				continue;
+			}
+			stats.sprites_drawn += 1;
 
 			let (sprite, variant) = match &drawable.kind
 			{
@@ -1503,10 +3095,13 @@ impl Map
 						0
 					};
 
+					// Each quarter turn of the view turns the sprites with it.
+					let dir = pos.dir + rotation as f32 * f32::pi() / 2.;
 					let variant = (num_orientations
-						- (((pos.dir.rem_euclid(2. * f32::pi()) + f32::pi() + window_size / 2.)
+						- (((dir.rem_euclid(2. * f32::pi()) + f32::pi() + window_size / 2.)
 							/ window_size) as i32 + num_orientations / 4)
-							% num_orientations) % num_orientations;
+							% num_orientations)
+						% num_orientations;
 					(sprite.clone(), offt + variant)
 				}
 				comps::DrawableKind::Fixed { sprite, variant } => (sprite.clone(), *variant),
@@ -1517,9 +3112,9 @@ impl Map
 					once,
 				} =>
 				{
-					let num_variants = state.get_sprite(&sprite).unwrap().num_variants();
+					let num_variants = state.get_sprite(sprite).unwrap().num_variants();
 					let variant =
-						(num_variants as f64 * (state.time() - start_time) / total_duration) as i32;
+						(num_variants as f64 * (self.time() - start_time) / total_duration) as i32;
 					let variant = if *once
 					{
 						utils::clamp(variant, 0, num_variants - 1)
@@ -1532,35 +3127,152 @@ impl Map
 				}
 			};
 
-			pos_and_sprite.push((pos.pos, xy, sprite, variant));
+			// Pilots should never lose sight of their planes.
+			if self.pilots.iter().any(|p| p.entity == id) && self.is_occluded(pos.pos, rotation)
+			{
+				silhouettes.push((xy, sprite.clone(), variant));
+			}
+			let mut light = if state.get_sprite(&sprite).unwrap().is_emissive()
+			{
+				Color::from_rgb_f(1., 1., 1.)
+			}
+			else
+			{
+				get_light(pos.pos, ambient, &fires)
+			};
+			let mut scale = 1.;
+			if let Ok(particle) = self.world.get::<&comps::Particle>(id)
+			{
+				if let Some(emitter) = state.get_emitter(&particle.emitter)
+				{
+					let f = ((self.time() - particle.creation_time) / emitter.lifetime) as f32;
+					light = light.modulate(emitter.get_color(f));
+					scale = emitter.get_scale(f);
+				}
+			}
+			let (_, view_y) = rotate_xy(pos.pos.x, pos.pos.y, rotation);
+			let depth = cell_depth(pos.pos.xy(), rotation);
+			items.push((
+				(depth, 2, pos.pos.z, view_y),
+				ViewItem::Sprite {
+					xy,
+					sprite,
+					variant,
+					light,
+					scale,
+				},
+			));
 		}
-		pos_and_sprite.sort_by(|(pos1, _, _, _), (pos2, _, _, _)| {
-			let yz1 = [pos1.z, pos1.y];
-			let yz2 = [pos2.z, pos2.y];
-
-			yz1.partial_cmp(&yz2).unwrap()
-		});
-		for (_, xy, sprite, variant) in pos_and_sprite
+		items.sort_by(|(key1, _), (key2, _)| key1.partial_cmp(key2).unwrap());
+		for (_, item) in items
+		{
+			match item
+			{
+				ViewItem::Tile { x, y } =>
+				{
+					self.draw_tile(
+						(x, y),
+						rotation,
+						Vector2::new(dx, dy),
+						(view_min, view_max),
+						ambient,
+						state,
+					);
+				}
+				ViewItem::Shadow { xy, size } =>
+				{
+					let sprite = "data/shadow.cfg";
+					let sprite = state
+						.get_sprite(sprite)
+						.unwrap_or_else(|| panic!("Could not find sprite: {}", sprite));
+					sprite.draw(
+						utils::round_point(xy + Vector2::new(dx, dy)),
+						size,
+						ambient,
+						state,
+					);
+				}
+				ViewItem::Sprite {
+					xy,
+					sprite,
+					variant,
+					light,
+					scale,
+				} =>
+				{
+					let sprite = state
+						.get_sprite(&sprite)
+						.unwrap_or_else(|| panic!("Could not find sprite: {}", sprite));
+					let xy = utils::round_point(xy + Vector2::new(dx, dy));
+					let variant = variant % sprite.num_variants();
+					if scale == 1.
+					{
+						sprite.draw(xy, variant, light, state);
+					}
+					else
+					{
+						sprite.draw_scaled(xy, variant, light, scale, state);
+					}
+				}
+			}
+		}
+		for (xy, sprite, variant) in silhouettes
 		{
 			let sprite = state
 				.get_sprite(&sprite)
-				.expect(&format!("Could not find sprite: {}", sprite));
+				.unwrap_or_else(|| panic!("Could not find sprite: {}", sprite));
 			sprite.draw(
 				utils::round_point(xy + Vector2::new(dx, dy)),
 				variant % sprite.num_variants(),
-				Color::from_rgb_f(1., 1., 1.),
+				Color::from_rgba_f(0.2, 0.4, 0.6, 0.6),
 				state,
 			);
 		}
 		state.core.hold_bitmap_drawing(false);
 
-		// UI
-		if let Ok(water_col) = self.world.get::<&comps::WaterCollector>(self.player)
+		// Fire glow, only visible as it gets dark.
+		let night = 1. - daylight;
+		if night > 0.
+		{
+			state
+				.core
+				.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::One);
+			for &fire in &fires
+			{
+				let xy = world_to_screen(fire, rotation);
+				let radius = FIRE_LIGHT_RADIUS * Vector2::new(90., 45.);
+				if !is_in_view((xy - radius, xy + radius), view_min, view_max)
+				{
+					continue;
+				}
+				for f in [1., 0.66, 0.33]
+				{
+					let a = 0.08 * night;
+					state.prim.draw_filled_ellipse(
+						xy.x + dx,
+						xy.y + dy,
+						radius.x * f,
+						radius.y * f,
+						Color::from_rgb_f(a, 0.6 * a, 0.3 * a),
+					);
+				}
+			}
+			state
+				.core
+				.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
+		}
+		stats.draw_time = state.core.get_time() - start_time;
+		stats
+	}
+
+	fn draw_hud(&self, pilot: &Pilot, x: f32, w: f32, state: &game_state::GameState)
+	{
+		if let Ok(water_col) = self.world.get::<&comps::WaterCollector>(pilot.entity)
 		{
 			state.core.draw_text(
 				&state.ui_font,
 				Color::from_rgb_f(0.4, 0.4, 0.8),
-				48.,
+				x + 48.,
 				24.,
 				FontAlign::Left,
 				"WATER",
@@ -1569,82 +3281,277 @@ impl Map
 			state.core.draw_text(
 				&state.number_font,
 				Color::from_rgb_f(0.4, 0.8, 0.4),
-				256.,
+				x + 256.,
 				24.,
 				FontAlign::Centre,
 				&format!("{:0>2}", water_col.water_amount),
 			);
 		}
 
-		state.core.draw_text(
-			&state.ui_font,
-			Color::from_rgb_f(0.8, 0.6, 0.4),
-			self.display_width - 256.,
-			24.,
-			FontAlign::Left,
-			"FIRES",
-		);
+		if let Ok(fuel) = self.world.get::<&comps::Fuel>(pilot.entity)
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				Color::from_rgb_f(0.8, 0.8, 0.4),
+				x + 48.,
+				64.,
+				FontAlign::Left,
+				"FUEL",
+			);
 
-		state.core.draw_text(
-			&state.number_font,
-			Color::from_rgb_f(0.4, 0.8, 0.8),
-			self.display_width - 64.,
-			24.,
-			FontAlign::Centre,
-			&format!("{:0>2}", self.num_fires),
-		);
+			draw_gauge(
+				x + 208.,
+				72.,
+				fuel.fuel / fuel.max_fuel,
+				Color::from_rgb_f(0.8, 0.8, 0.4),
+				state,
+			);
+		}
 
-		if self.ui_state == UIState::Regular
+		if let Ok(health) = self.world.get::<&comps::Health>(pilot.entity)
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				Color::from_rgb_f(0.8, 0.4, 0.4),
+				x + 48.,
+				104.,
+				FontAlign::Left,
+				"HEALTH",
+			);
+
+			draw_gauge(
+				x + 208.,
+				112.,
+				health.health / health.max_health,
+				Color::from_rgb_f(0.8, 0.4, 0.4),
+				state,
+			);
+		}
+
+		if pilot.autopilot
 		{
-			if self.world.contains(self.player)
+			state.core.draw_text(
+				&state.ui_font,
+				Color::from_rgb_f(0.4, 0.8, 0.8),
+				x + 48.,
+				144.,
+				FontAlign::Left,
+				"AUTOPILOT",
+			);
+		}
+
+		if self.ui_state != UIState::Regular
+		{
+			return;
+		}
+
+		if self.world.contains(pilot.entity)
+		{
+			if pilot.collision_alert
 			{
-				if self.collision_alert
+				let y = if self.views.len() > 1
 				{
-					state.core.draw_text(
-						&state.ui_font,
-						Color::from_rgb_f(0.9, 0.3, 0.3),
-						self.display_width / 2.,
-						self.display_height - 48.,
-						FontAlign::Centre,
-						"!! COLLISION IMMINENT !!",
-					);
+					self.display_height - 96.
 				}
-			}
-			else
-			{
-				state.prim.draw_filled_rectangle(
-					0.,
-					0.,
-					self.display_width,
-					self.display_height,
-					Color::from_rgba_f(0., 0., 0., 0.5),
-				);
-
+				else
+				{
+					self.display_height - 48.
+				};
 				state.core.draw_text(
 					&state.ui_font,
-					Color::from_rgb_f(0.7, 0.7, 0.9),
-					self.display_width / 2.,
-					self.display_height / 2. - 24.,
+					Color::from_rgb_f(0.9, 0.3, 0.3),
+					x + w / 2.,
+					y,
 					FontAlign::Centre,
-					"CRASHED!",
+					"!! COLLISION IMMINENT !!",
 				);
+			}
+		}
+		else
+		{
+			state.prim.draw_filled_rectangle(
+				x,
+				0.,
+				x + w,
+				self.display_height,
+				Color::from_rgba_f(0., 0., 0., 0.5),
+			);
+
+			state.core.draw_text(
+				&state.ui_font,
+				Color::from_rgb_f(0.7, 0.7, 0.9),
+				x + w / 2.,
+				self.display_height / 2. - 24.,
+				FontAlign::Centre,
+				"CRASHED!",
+			);
+			if state.net.is_none()
+			{
 				state.core.draw_text(
 					&state.ui_font,
 					Color::from_rgb_f(0.7, 0.7, 0.9),
-					self.display_width / 2.,
+					x + w / 2.,
 					self.display_height / 2. + 24.,
 					FontAlign::Centre,
 					&format!(
 						"PRESS {} TO RESTART",
 						state
-							.options
 							.controls
+							.get_controls()
 							.get_action_string(controls::Action::Restart)
 							.to_uppercase()
 					),
 				);
 			}
+		}
+	}
+
+	/// The simulation time, in seconds since the map started.
+	fn time(&self) -> f64
+	{
+		self.ticks * utils::DT as f64
+	}
+
+	/// The hour of the day, from 0 to 24.
+	fn get_hour(&self, state: &game_state::GameState) -> f32
+	{
+		if state.options.day_length <= 0.
+		{
+			return self.start_hour;
+		}
+		let days = self.time() as f32 / state.options.day_length;
+		(self.start_hour + 24. * days).rem_euclid(24.)
+	}
+
+	pub fn get_draw_stats(&self) -> &DrawStats
+	{
+		&self.draw_stats
+	}
 
+	pub fn draw(&mut self, state: &game_state::GameState) -> Result<()>
+	{
+		state.core.clear_to_color(Color::from_rgb_f(0., 0., 0.));
+
+		let view_width = self.display_width / self.views.len() as f32;
+		let mut draw_stats = DrawStats::default();
+		for (i, &pilot) in self.views.iter().enumerate()
+		{
+			let pilot = &self.pilots[pilot];
+			let x = i as f32 * view_width;
+			state.core.set_clipping_rectangle(
+				x as i32,
+				0,
+				view_width as i32,
+				self.display_height as i32,
+			);
+			let cx = x + view_width / 2.;
+			let cy = self.display_height / 2.;
+			let camera = pilot.camera.interpolate(state.interpolation);
+
+			// Zoom around the center of the view.
+			let mut transform = Transform::identity();
+			transform.translate(-cx, -cy);
+			transform.scale(camera.zoom, camera.zoom);
+			transform.translate(cx, cy);
+			state.core.use_transform(&transform);
+			let view_stats =
+				self.draw_view(&camera, cx, cy, view_width, self.display_height, state);
+			draw_stats.add(&view_stats);
+			state.core.use_transform(&Transform::identity());
+
+			self.draw_hud(pilot, x, view_width, state);
+			if let Some(offset) = state.get_player_controls(i).get_mouse_flight_offset()
+			{
+				if let (UIState::Regular, Ok(mut query)) = (
+					self.ui_state,
+					self.world
+						.query_one::<(&comps::Position, Option<&comps::PrevPosition>)>(
+							pilot.entity,
+						),
+				)
+				{
+					if let Some((pos, prev)) = query.get()
+					{
+						// The camera trails the plane, so the reticle follows it.
+						let pos = interpolate_position(pos, prev, state.interpolation);
+						let rotation = camera.rotation;
+						let camera_xy = world_to_screen(camera.pos, rotation) + camera.look_offset;
+						let xy = (world_to_screen(pos.pos, rotation) - camera_xy) * camera.zoom;
+						draw_reticle(cx + xy.x, cy + xy.y, offset, state);
+					}
+				}
+			}
+		}
+		state.core.reset_clipping_rectangle();
+		self.draw_stats = draw_stats;
+
+		for i in 1..self.views.len()
+		{
+			let x = i as f32 * view_width;
+			state.prim.draw_line(
+				x,
+				0.,
+				x,
+				self.display_height,
+				Color::from_rgb_f(0.8, 0.6, 0.4),
+				2.,
+			);
+		}
+
+		let (fires_x, fires_y) = if self.views.len() > 1
+		{
+			(self.display_width / 2. - 96., self.display_height - 48.)
+		}
+		else
+		{
+			(self.display_width - 256., 24.)
+		};
+		if self.views.len() > 1
+		{
+			state.prim.draw_filled_rectangle(
+				fires_x - 16.,
+				fires_y - 8.,
+				fires_x + 224.,
+				fires_y + 40.,
+				Color::from_rgba_f(0., 0., 0., 0.5),
+			);
+		}
+
+		state.core.draw_text(
+			&state.ui_font,
+			Color::from_rgb_f(0.8, 0.6, 0.4),
+			fires_x,
+			fires_y,
+			FontAlign::Left,
+			"FIRES",
+		);
+
+		state.core.draw_text(
+			&state.number_font,
+			Color::from_rgb_f(0.4, 0.8, 0.8),
+			fires_x + 192.,
+			fires_y,
+			FontAlign::Centre,
+			&format!("{:0>2}", self.num_fires),
+		);
+
+		if self.ui_state == UIState::Regular
+		{
+			if state
+				.net
+				.as_ref()
+				.map(|n| n.stalled_ticks() > 60)
+				.unwrap_or(false)
+			{
+				state.core.draw_text(
+					&state.ui_font,
+					Color::from_rgb_f(0.7, 0.7, 0.9),
+					self.display_width / 2.,
+					self.display_height / 2. - 24.,
+					FontAlign::Centre,
+					"WAITING FOR OTHER PILOTS",
+				);
+			}
 			if self.show_minimap
 			{
 				let cx = self.display_width / 2.;
@@ -1661,19 +3568,22 @@ impl Map
 					Color::from_rgba_f(0., 0., 0.3, 0.5),
 				);
 
-				let f = (0.5 + 0.5 * (state.time() * 10.).sin()) as f32;
+				let f = (0.5 + 0.5 * (self.time() * 10.).sin()) as f32;
 
 				let w = w - 32.;
 
-				if let Ok(pos) = self.world.get::<&comps::Position>(self.player)
+				for pilot in &self.pilots
 				{
-					let color = Color::from_rgba_f(0.1, 0.9, 0.1, 0.5);
-					state.prim.draw_filled_circle(
-						ox + pos.pos.x / self.size as f32 * w,
-						oy + pos.pos.y / self.size as f32 * w,
-						6. * f + 8. * (1. - f),
-						color,
-					);
+					if let Ok(pos) = self.world.get::<&comps::Position>(pilot.entity)
+					{
+						let color = Color::from_rgba_f(0.1, 0.9, 0.1, 0.5);
+						state.prim.draw_filled_circle(
+							ox + pos.pos.x / self.size as f32 * w,
+							oy + pos.pos.y / self.size as f32 * w,
+							6. * f + 8. * (1. - f),
+							color,
+						);
+					}
 				}
 
 				for (_, (pos, mushroom)) in self
@@ -1692,6 +3602,34 @@ impl Map
 					}
 				}
 
+				for (_, (pos, _)) in self
+					.world
+					.query_mut::<(&comps::Position, &comps::Wingman)>()
+				{
+					let color = Color::from_rgba_f(0.1, 0.6, 0.9, 0.5);
+					state.prim.draw_filled_circle(
+						ox + pos.pos.x / self.size as f32 * w,
+						oy + pos.pos.y / self.size as f32 * w,
+						4. * f + 6. * (1. - f),
+						color,
+					);
+				}
+
+				for (_, (pos, airstrip)) in self
+					.world
+					.query_mut::<(&comps::Position, &comps::Airstrip)>()
+				{
+					let color = Color::from_rgba_f(0.8, 0.8, 0.4, 0.5);
+					let he = airstrip.half_extents;
+					state.prim.draw_filled_rectangle(
+						ox + (pos.pos.x - he.x) / self.size as f32 * w,
+						oy + (pos.pos.y - he.y) / self.size as f32 * w,
+						ox + (pos.pos.x + he.x) / self.size as f32 * w,
+						oy + (pos.pos.y + he.y) / self.size as f32 * w,
+						color,
+					);
+				}
+
 				for (_, (pos, _)) in self
 					.world
 					.query_mut::<(&comps::Position, &comps::Obelisk)>()
@@ -1716,62 +3654,60 @@ impl Map
 				Color::from_rgba_f(0., 0., 0., 0.5),
 			);
 
-			state.core.draw_text(
-				&state.ui_font,
-				Color::from_rgb_f(0.7, 0.7, 0.9),
-				self.display_width / 2.,
-				self.display_height / 2. - 48. * 2.,
-				FontAlign::Centre,
-				&format!("YOU DID IT!",),
-			);
-
-			state.core.draw_text(
-				&state.ui_font,
-				Color::from_rgb_f(0.7, 0.7, 0.9),
-				self.display_width / 2.,
-				self.display_height / 2. - 48. * 1.,
-				FontAlign::Centre,
-				&format!("YOU DUMPED {} TONS OF WATER", self.num_blobs,),
-			);
-
-			state.core.draw_text(
-				&state.ui_font,
-				Color::from_rgb_f(0.7, 0.7, 0.9),
-				self.display_width / 2.,
-				self.display_height / 2. + 48. * 0.,
-				FontAlign::Centre,
-				&format!("YOU EXTINGUISHED {} MUSHROOMS", self.num_extinguished,),
-			);
-
-			let accuracy = self.num_extinguished as f32 / self.num_blobs as f32;
-			state.core.draw_text(
-				&state.ui_font,
-				Color::from_rgb_f(0.7, 0.7, 0.9),
-				self.display_width / 2.,
-				self.display_height / 2. + 48. * 1.,
-				FontAlign::Centre,
-				&format!(
+			let mut lines = vec!["YOU DID IT!".to_string()];
+			if self.pilots.len() == 1
+			{
+				let pilot = &self.pilots[0];
+				let accuracy = pilot.num_extinguished as f32 / pilot.num_blobs as f32;
+				lines.push(format!("YOU DUMPED {} TONS OF WATER", pilot.num_blobs));
+				lines.push(format!(
+					"YOU EXTINGUISHED {} MUSHROOMS",
+					pilot.num_extinguished
+				));
+				lines.push(format!(
 					"YOUR ACCURACY WAS {:.2}{}",
 					accuracy,
 					if accuracy > 0.9 { " WOW!" } else { "" }
-				),
-			);
-
-			state.core.draw_text(
-				&state.ui_font,
-				Color::from_rgb_f(0.7, 0.7, 0.9),
-				self.display_width / 2.,
-				self.display_height / 2. + 48. * 2.,
-				FontAlign::Centre,
-				&format!(
+				));
+			}
+			else
+			{
+				for (i, pilot) in self.pilots.iter().enumerate()
+				{
+					let accuracy = pilot.num_extinguished as f32 / pilot.num_blobs as f32;
+					lines.push(format!(
+						"P{}: {} TONS {} MUSHROOMS {:.2}",
+						i + 1,
+						pilot.num_blobs,
+						pilot.num_extinguished,
+						accuracy
+					));
+				}
+			}
+			if state.net.is_none()
+			{
+				lines.push(format!(
 					"PRESS {} TO TRY AGAIN",
 					state
-						.options
 						.controls
+						.get_controls()
 						.get_action_string(controls::Action::Restart)
 						.to_uppercase()
-				),
-			);
+				));
+			}
+
+			let start_y = self.display_height / 2. - 48. * (lines.len() / 2) as f32;
+			for (i, line) in lines.iter().enumerate()
+			{
+				state.core.draw_text(
+					&state.ui_font,
+					Color::from_rgb_f(0.7, 0.7, 0.9),
+					self.display_width / 2.,
+					start_y + 48. * i as f32,
+					FontAlign::Centre,
+					line,
+				);
+			}
 		}
 		else
 		{
@@ -1791,3 +3727,46 @@ impl Map
 		Ok(())
 	}
 }
+
+#[test]
+fn test_thin_ridge()
+{
+	// A ridge along x = 3.5, the path samples either side of it are well clear.
+	let size = 8;
+	let mut heightmap = vec![0; size * size];
+	for y in 0..size
+	{
+		heightmap[4 + y * size] = 10;
+	}
+	let mushrooms = vec![None; size * size];
+	let pos = Point3::new(0.05, 3., 10.3);
+	let vel = Vector3::new(1., 0., 0.);
+
+	let time = predict_collision(&heightmap, &mushrooms, pos, vel, 0., 5.).unwrap();
+	assert!((time - 3.45).abs() < 0.05);
+	let ground = terrain_ahead(&heightmap, &mushrooms, pos, vel, 0., 5.);
+	assert!((ground - 10.).abs() < 1e-3);
+}
+
+#[test]
+fn test_tile_rotation()
+{
+	// Where the corners of a flat tile are in the sprite, in `TILE_CORNERS` order.
+	let sprite_corners = [(64., 64.), (128., 96.), (0., 96.), (64., 128.)];
+	let (x, y, z) = (3, 5, 2);
+	for rotation in 0..4
+	{
+		// The cell's origin corner, wherever it ends up on screen.
+		let k = TILE_CORNERS[rotation as usize]
+			.iter()
+			.position(|&c| c == (0, 0))
+			.unwrap();
+		let (sx, sy) = sprite_corners[k];
+		let corner = tile_position(x, y, z, rotation) + Vector2::new(sx, sy);
+		let expected = world_to_screen(
+			Point3::new(x as f32 - 0.5, y as f32 - 0.5, z as f32),
+			rotation,
+		);
+		assert!((corner - expected).norm() < 1e-3, "rotation {}", rotation);
+	}
+}
@@ -1,5 +1,5 @@
 use crate::error::Result;
-use crate::{components, controls, game_state, map, ui, utils};
+use crate::{components, controls, game_state, map, net, ui, utils};
 
 use allegro::*;
 use allegro_sys::*;
@@ -32,6 +32,9 @@ impl Menu
 		state.cache_sprite("data/title.cfg")?;
 		state.paused = false;
 		state.hide_mouse = false;
+		state.time_scale = 1.;
+		state.shader_uniforms.clear();
+		state.net = None;
 		state.sfx.cache_sample("data/ui1.ogg")?;
 		state.sfx.cache_sample("data/ui2.ogg")?;
 
@@ -42,35 +45,61 @@ impl Menu
 		dbg!(seed);
 
 		Ok(Self {
-			display_width: display_width,
-			display_height: display_height,
+			display_width,
+			display_height,
 			subscreens: vec![ui::SubScreen::MainMenu(ui::MainMenu::new(
 				display_width,
 				display_height,
 			))],
 			switch_time: 0.,
-			seed: seed,
+			seed,
 			do_switch: false,
 		})
 	}
 
+	/// Lays the menu out for a new buffer size. This happens from the options
+	/// menu, so that is where the player is left.
+	pub fn resize(&mut self, state: &game_state::GameState, display_width: f32, display_height: f32)
+	{
+		self.display_width = display_width;
+		self.display_height = display_height;
+		self.subscreens = vec![
+			ui::SubScreen::MainMenu(ui::MainMenu::new(display_width, display_height)),
+			ui::SubScreen::OptionsMenu(ui::OptionsMenu::new(state, display_width, display_height)),
+		];
+	}
+
 	pub fn input(
 		&mut self, event: &Event, state: &mut game_state::GameState,
 	) -> Result<Option<game_state::NextScreen>>
 	{
-		if let Event::KeyDown {
-			keycode: KeyCode::Escape,
-			..
-		} = event
+		state.controls.decode_event(event);
+		if let Event::TimerTick { .. } = event
 		{
-			if self.subscreens.len() > 1
+			state.controls.update();
+			if let Some(net) = state.net.as_mut()
 			{
-				state.sfx.play_sound("data/ui2.ogg").unwrap();
-				self.subscreens.pop().unwrap();
-				self.do_switch = false;
-				return Ok(None);
+				if let Some(settings) = net.poll_lobby()?
+				{
+					return Ok(Some(game_state::NextScreen::Game {
+						seed: settings.seed,
+						restart_music: true,
+					}));
+				}
 			}
 		}
+		if state
+			.controls
+			.get_event_actions()
+			.contains(&controls::Action::MenuBack)
+			&& !self.subscreens.last().unwrap().is_capturing_input()
+			&& self.subscreens.len() > 1
+		{
+			state.sfx.play_sound("data/ui2.ogg").unwrap();
+			self.pop_subscreen(state);
+			self.do_switch = false;
+			return Ok(None);
+		}
 		if let Some(action) = self.subscreens.last_mut().unwrap().input(state, event)
 		{
 			match action
@@ -97,16 +126,45 @@ impl Menu
 				}
 				ui::Action::Start =>
 				{
+					if let Some(net) = state.net.as_mut()
+					{
+						if !net.is_host()
+						{
+							return Ok(None);
+						}
+						net.start(self.seed, &state.options)?;
+					}
 					return Ok(Some(game_state::NextScreen::Game {
 						seed: self.seed,
 						restart_music: true,
-					}))
+					}));
+				}
+				ui::Action::Host =>
+				{
+					state.net = None;
+					match net::Session::host(state.options.net_port as u16)
+					{
+						Ok(session) => state.net = Some(session),
+						Err(e) => println!("{}", e),
+					}
+				}
+				ui::Action::Join =>
+				{
+					state.net = None;
+					match net::Session::join(
+						&state.options.host_address,
+						state.options.net_port as u16,
+					)
+					{
+						Ok(session) => state.net = Some(session),
+						Err(e) => println!("{}", e),
+					}
 				}
 				ui::Action::Quit => return Ok(Some(game_state::NextScreen::Quit)),
 				ui::Action::Back =>
 				{
 					self.do_switch = false;
-					self.subscreens.pop().unwrap();
+					self.pop_subscreen(state);
 				}
 				_ => (),
 			}
@@ -114,13 +172,21 @@ impl Menu
 		Ok(None)
 	}
 
+	fn pop_subscreen(&mut self, state: &mut game_state::GameState)
+	{
+		if let Some(ui::SubScreen::NetworkMenu(_)) = self.subscreens.pop()
+		{
+			state.net = None;
+		}
+	}
+
 	pub fn draw(&mut self, state: &game_state::GameState) -> Result<()>
 	{
 		state.core.clear_to_color(Color::from_rgb_f(0., 0., 0.));
 		let sprite = "data/title.cfg";
 		let sprite = state
 			.get_sprite(sprite)
-			.expect(&format!("Could not find sprite: {}", sprite));
+			.unwrap_or_else(|| panic!("Could not find sprite: {}", sprite));
 
 		let h = 64.;
 		if self.do_switch
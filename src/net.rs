@@ -0,0 +1,623 @@
+use crate::error::{Error, Result};
+use crate::{controls, game_state};
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::{FromStr, SplitWhitespace};
+
+pub const MAX_PLAYERS: usize = 4;
+// How many ticks in the future local inputs are scheduled for.
+const INPUT_DELAY: i64 = 6;
+// How many past ticks are resent with every packet, to cover for packet loss.
+const REDUNDANCY: i64 = 16;
+const JOIN_RESEND_TICKS: i32 = 60;
+
+pub const FLIGHT_ACTIONS: [controls::Action; 5] = [
+	controls::Action::TurnLeft,
+	controls::Action::TurnRight,
+	controls::Action::Ascend,
+	controls::Action::Descend,
+	controls::Action::DropWater,
+];
+
+pub type InputFrame = [f32; FLIGHT_ACTIONS.len()];
+
+pub fn input_frame_from_controls(controls: &controls::ControlsHandler) -> InputFrame
+{
+	let mut frame = [0.; FLIGHT_ACTIONS.len()];
+	for (value, &action) in frame.iter_mut().zip(FLIGHT_ACTIONS.iter())
+	{
+		*value = controls.get_action_state(action);
+	}
+	frame
+}
+
+pub fn get_action_state(frame: &InputFrame, action: controls::Action) -> f32
+{
+	FLIGHT_ACTIONS
+		.iter()
+		.position(|&a| a == action)
+		.map(|i| frame[i])
+		.unwrap_or(0.)
+}
+
+pub fn set_action_state(frame: &mut InputFrame, action: controls::Action, value: f32)
+{
+	if let Some(i) = FLIGHT_ACTIONS.iter().position(|&a| a == action)
+	{
+		frame[i] = value;
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameSettings
+{
+	pub seed: u64,
+	pub num_players: usize,
+	pub map_size: i32,
+	pub fire_start_probability: f32,
+	pub fire_spread_probability: f32,
+	pub obelisk_factor: f32,
+	pub water_factor: f32,
+	pub num_wingmen: i32,
+	pub night_mission: bool,
+}
+
+impl GameSettings
+{
+	pub fn new(seed: u64, num_players: usize, options: &game_state::Options) -> Self
+	{
+		Self {
+			seed,
+			num_players,
+			map_size: options.map_size,
+			fire_start_probability: options.fire_start_probability,
+			fire_spread_probability: options.fire_spread_probability,
+			obelisk_factor: options.obelisk_factor,
+			water_factor: options.water_factor,
+			num_wingmen: options.num_wingmen,
+			night_mission: options.night_mission,
+		}
+	}
+
+	pub fn apply(&self, options: &mut game_state::Options)
+	{
+		options.map_size = self.map_size;
+		options.fire_start_probability = self.fire_start_probability;
+		options.fire_spread_probability = self.fire_spread_probability;
+		options.obelisk_factor = self.obelisk_factor;
+		options.water_factor = self.water_factor;
+		options.num_wingmen = self.num_wingmen;
+		options.night_mission = self.night_mission;
+	}
+
+	fn encode(&self) -> String
+	{
+		format!(
+			"START {} {} {} {} {} {} {} {} {}",
+			self.seed,
+			self.num_players,
+			self.map_size,
+			self.fire_start_probability,
+			self.fire_spread_probability,
+			self.obelisk_factor,
+			self.water_factor,
+			self.num_wingmen,
+			self.night_mission
+		)
+	}
+
+	fn decode(tokens: &mut SplitWhitespace) -> Option<Self>
+	{
+		Some(Self {
+			seed: parse(tokens)?,
+			num_players: parse(tokens)?,
+			map_size: parse(tokens)?,
+			fire_start_probability: parse(tokens)?,
+			fire_spread_probability: parse(tokens)?,
+			obelisk_factor: parse(tokens)?,
+			water_factor: parse(tokens)?,
+			num_wingmen: parse(tokens)?,
+			night_mission: parse(tokens)?,
+		})
+	}
+}
+
+fn parse<T: FromStr>(tokens: &mut SplitWhitespace) -> Option<T>
+{
+	tokens.next()?.parse().ok()
+}
+
+fn parse_frame(tokens: &mut SplitWhitespace) -> Option<InputFrame>
+{
+	let mut frame = [0.; FLIGHT_ACTIONS.len()];
+	for value in &mut frame
+	{
+		*value = parse(tokens)?;
+	}
+	Some(frame)
+}
+
+fn encode_frame(message: &mut String, frame: &InputFrame)
+{
+	for value in frame
+	{
+		message.push_str(&format!(" {}", value));
+	}
+}
+
+fn io_error(message: &str, error: io::Error) -> Error
+{
+	Error::new(message.to_string(), Some(Box::new(error)))
+}
+
+/// A lockstep session. The host (player 0) collects the inputs of every
+/// player for each tick and broadcasts the combined frames; every peer only
+/// advances its simulation once it has the frame for its current tick.
+pub struct Session
+{
+	socket: UdpSocket,
+	// None if we are the host.
+	host: Option<SocketAddr>,
+	// Host only: the clients, in player order starting from player 1.
+	peers: Vec<SocketAddr>,
+	peer_started: Vec<bool>,
+	player: Option<usize>,
+	settings: Option<GameSettings>,
+	tick: i64,
+	last_scheduled: i64,
+	stalled_ticks: i32,
+	join_resend: i32,
+	local_inputs: BTreeMap<i64, InputFrame>,
+	pending: BTreeMap<i64, Vec<Option<InputFrame>>>,
+	frames: BTreeMap<i64, Vec<InputFrame>>,
+}
+
+impl Session
+{
+	fn new(socket: UdpSocket, host: Option<SocketAddr>, player: Option<usize>) -> Result<Self>
+	{
+		socket
+			.set_nonblocking(true)
+			.map_err(|e| io_error("Couldn't make socket non-blocking", e))?;
+		Ok(Self {
+			socket,
+			host,
+			peers: vec![],
+			peer_started: vec![],
+			player,
+			settings: None,
+			tick: 0,
+			last_scheduled: INPUT_DELAY - 1,
+			stalled_ticks: 0,
+			join_resend: 0,
+			local_inputs: BTreeMap::new(),
+			pending: BTreeMap::new(),
+			frames: BTreeMap::new(),
+		})
+	}
+
+	pub fn host(port: u16) -> Result<Self>
+	{
+		let socket = UdpSocket::bind(("0.0.0.0", port))
+			.map_err(|e| io_error(&format!("Couldn't listen on port {}", port), e))?;
+		Session::new(socket, None, Some(0))
+	}
+
+	pub fn join(address: &str, default_port: u16) -> Result<Self>
+	{
+		let address = if address.contains(':')
+		{
+			address.to_string()
+		}
+		else
+		{
+			format!("{}:{}", address, default_port)
+		};
+		let host = address
+			.to_socket_addrs()
+			.map_err(|e| io_error(&format!("Couldn't resolve '{}'", address), e))?
+			.next()
+			.ok_or_else(|| format!("Couldn't resolve '{}'", address))?;
+		let socket =
+			UdpSocket::bind(("0.0.0.0", 0)).map_err(|e| io_error("Couldn't open socket", e))?;
+		Session::new(socket, Some(host), None)
+	}
+
+	pub fn is_host(&self) -> bool
+	{
+		self.host.is_none()
+	}
+
+	pub fn player(&self) -> usize
+	{
+		self.player.unwrap_or(0)
+	}
+
+	/// The settings of the started game, as chosen by the host.
+	pub fn get_settings(&self) -> Option<&GameSettings>
+	{
+		self.settings.as_ref()
+	}
+
+	pub fn num_players(&self) -> usize
+	{
+		self.settings
+			.as_ref()
+			.map(|s| s.num_players)
+			.unwrap_or(self.peers.len() + 1)
+	}
+
+	pub fn stalled_ticks(&self) -> i32
+	{
+		self.stalled_ticks
+	}
+
+	pub fn status(&self) -> String
+	{
+		if self.is_host()
+		{
+			format!("HOSTING, {} PLAYERS", self.peers.len() + 1)
+		}
+		else if let Some(player) = self.player
+		{
+			format!("JOINED AS PLAYER {}", player + 1)
+		}
+		else
+		{
+			"CONNECTING...".into()
+		}
+	}
+
+	/// Host only. Fixes the player count and tells the clients to start.
+	pub fn start(&mut self, seed: u64, options: &game_state::Options) -> Result<GameSettings>
+	{
+		let settings = GameSettings::new(seed, self.peers.len() + 1, options);
+		self.peer_started = vec![false; self.peers.len()];
+		self.start_lockstep(settings.clone());
+		self.send_starts()?;
+		Ok(settings)
+	}
+
+	/// Handles the lobby traffic. Returns the game settings once a client has
+	/// been told to start.
+	pub fn poll_lobby(&mut self) -> Result<Option<GameSettings>>
+	{
+		if let Some(host) = self.host
+		{
+			if self.player.is_none()
+			{
+				if self.join_resend <= 0
+				{
+					self.send("JOIN", host)?;
+					self.join_resend = JOIN_RESEND_TICKS;
+				}
+				self.join_resend -= 1;
+			}
+		}
+		let started = self.settings.is_some();
+		self.receive()?;
+		if !started
+		{
+			Ok(self.settings.clone())
+		}
+		else
+		{
+			Ok(None)
+		}
+	}
+
+	/// Schedules the local input and returns the inputs of every player for
+	/// the current tick, if they've all arrived. The simulation should not
+	/// advance otherwise.
+	pub fn step(&mut self, local_input: InputFrame) -> Result<Option<Vec<InputFrame>>>
+	{
+		let player = self.player();
+		let scheduled = self.tick + INPUT_DELAY;
+		if scheduled > self.last_scheduled
+		{
+			self.last_scheduled = scheduled;
+			self.local_inputs.insert(scheduled, local_input);
+			if self.is_host()
+			{
+				self.add_input(scheduled, player, local_input);
+			}
+		}
+		let oldest = self.last_scheduled - REDUNDANCY;
+		self.local_inputs = self.local_inputs.split_off(&oldest);
+
+		self.receive()?;
+
+		if let Some(host) = self.host
+		{
+			let mut message = format!("INPUT {}", player);
+			for (tick, frame) in &self.local_inputs
+			{
+				message.push_str(&format!(" {}", tick));
+				encode_frame(&mut message, frame);
+			}
+			self.send(&message, host)?;
+		}
+		else
+		{
+			self.send_starts()?;
+			self.broadcast_frames()?;
+		}
+
+		if let Some(frame) = self.frames.get(&self.tick).cloned()
+		{
+			self.tick += 1;
+			self.stalled_ticks = 0;
+			let newest = self.frames.keys().next_back().cloned().unwrap_or(0);
+			let keep_from = if self.is_host()
+			{
+				self.tick.min(newest + 1 - REDUNDANCY)
+			}
+			else
+			{
+				self.tick
+			};
+			self.frames = self.frames.split_off(&keep_from);
+			Ok(Some(frame))
+		}
+		else
+		{
+			self.stalled_ticks += 1;
+			Ok(None)
+		}
+	}
+
+	fn start_lockstep(&mut self, settings: GameSettings)
+	{
+		// The first few ticks happen before anyone could have sent anything.
+		for tick in 0..INPUT_DELAY
+		{
+			self.frames
+				.insert(tick, vec![[0.; FLIGHT_ACTIONS.len()]; settings.num_players]);
+		}
+		self.settings = Some(settings);
+	}
+
+	fn add_input(&mut self, tick: i64, player: usize, frame: InputFrame)
+	{
+		let num_players = self.num_players();
+		if tick < self.tick || self.frames.contains_key(&tick) || player >= num_players
+		{
+			return;
+		}
+		let inputs = self
+			.pending
+			.entry(tick)
+			.or_insert_with(|| vec![None; num_players]);
+		inputs[player] = Some(frame);
+		if inputs.iter().all(|i| i.is_some())
+		{
+			let inputs = self.pending.remove(&tick).unwrap();
+			self.frames
+				.insert(tick, inputs.into_iter().map(|i| i.unwrap()).collect());
+		}
+	}
+
+	fn send(&self, message: &str, address: SocketAddr) -> Result<()>
+	{
+		match self.socket.send_to(message.as_bytes(), address)
+		{
+			Ok(_) => Ok(()),
+			// The peer might not be listening yet, it'll get the resent copy.
+			Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+			Err(e) => Err(io_error("Couldn't send packet", e)),
+		}
+	}
+
+	fn send_starts(&self) -> Result<()>
+	{
+		if let Some(settings) = &self.settings
+		{
+			let message = settings.encode();
+			for (&peer, &started) in self.peers.iter().zip(self.peer_started.iter())
+			{
+				if !started
+				{
+					self.send(&message, peer)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn broadcast_frames(&self) -> Result<()>
+	{
+		if self.frames.is_empty() || self.peers.is_empty()
+		{
+			return Ok(());
+		}
+		let mut message = format!("FRAME {}", self.num_players());
+		for (tick, inputs) in self.frames.iter().rev().take(REDUNDANCY as usize)
+		{
+			message.push_str(&format!(" {}", tick));
+			for frame in inputs
+			{
+				encode_frame(&mut message, frame);
+			}
+		}
+		for &peer in &self.peers
+		{
+			self.send(&message, peer)?;
+		}
+		Ok(())
+	}
+
+	fn receive(&mut self) -> Result<()>
+	{
+		let mut buf = [0; 4096];
+		loop
+		{
+			let (len, address) = match self.socket.recv_from(&mut buf)
+			{
+				Ok(r) => r,
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+				// Windows reports ICMP port unreachable this way, ignore it.
+				Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+				Err(e) => return Err(io_error("Couldn't receive packet", e)),
+			};
+			if let Ok(message) = std::str::from_utf8(&buf[..len])
+			{
+				self.handle_message(message, address)?;
+			}
+		}
+	}
+
+	fn handle_message(&mut self, message: &str, address: SocketAddr) -> Result<()>
+	{
+		let mut tokens = message.split_whitespace();
+		match (tokens.next(), self.host)
+		{
+			(Some("JOIN"), None) =>
+			{
+				let idx = self.peers.iter().position(|&p| p == address);
+				let idx = match idx
+				{
+					Some(idx) => idx,
+					None =>
+					{
+						if self.settings.is_some() || self.peers.len() + 1 >= MAX_PLAYERS
+						{
+							return Ok(());
+						}
+						self.peers.push(address);
+						self.peers.len() - 1
+					}
+				};
+				self.send(&format!("WELCOME {}", idx + 1), address)?;
+			}
+			(Some("INPUT"), None) =>
+			{
+				let player: usize = match parse(&mut tokens)
+				{
+					Some(player) => player,
+					None => return Ok(()),
+				};
+				if player == 0 || self.peers.get(player - 1) != Some(&address)
+				{
+					return Ok(());
+				}
+				if let Some(started) = self.peer_started.get_mut(player - 1)
+				{
+					*started = true;
+				}
+				while let Some(tick) = parse(&mut tokens)
+				{
+					match parse_frame(&mut tokens)
+					{
+						Some(frame) => self.add_input(tick, player, frame),
+						None => break,
+					}
+				}
+			}
+			(Some("WELCOME"), Some(host)) if host == address && self.player.is_none() =>
+			{
+				self.player = parse(&mut tokens);
+			}
+			(Some("START"), Some(host))
+				if host == address && self.settings.is_none() && self.player.is_some() =>
+			{
+				if let Some(settings) = GameSettings::decode(&mut tokens)
+				{
+					self.start_lockstep(settings);
+				}
+			}
+			(Some("FRAME"), Some(host)) if host == address =>
+			{
+				let num_players: usize = match parse(&mut tokens)
+				{
+					Some(num_players) => num_players,
+					None => return Ok(()),
+				};
+				if num_players != self.num_players()
+				{
+					return Ok(());
+				}
+				'frames: while let Some(tick) = parse::<i64>(&mut tokens)
+				{
+					let mut inputs = Vec::with_capacity(num_players);
+					for _ in 0..num_players
+					{
+						match parse_frame(&mut tokens)
+						{
+							Some(frame) => inputs.push(frame),
+							None => break 'frames,
+						}
+					}
+					if tick >= self.tick
+					{
+						self.frames.insert(tick, inputs);
+					}
+				}
+			}
+			_ => (),
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn test_loopback_lockstep()
+{
+	let mut host = Session::host(0).unwrap();
+	let port = host.socket.local_addr().unwrap().port();
+	let mut client = Session::join("127.0.0.1", port).unwrap();
+
+	for _ in 0..100
+	{
+		client.poll_lobby().unwrap();
+		host.poll_lobby().unwrap();
+		if client.player.is_some()
+		{
+			break;
+		}
+		std::thread::sleep(std::time::Duration::from_millis(1));
+	}
+	assert_eq!(client.player(), 1);
+
+	let options = game_state::Options::default();
+	let settings = host.start(1234, &options).unwrap();
+	let mut client_settings = None;
+	for _ in 0..100
+	{
+		client_settings = client.poll_lobby().unwrap();
+		if client_settings.is_some()
+		{
+			break;
+		}
+		std::thread::sleep(std::time::Duration::from_millis(1));
+	}
+	assert_eq!(client_settings, Some(settings));
+
+	let mut host_frames = vec![];
+	let mut client_frames = vec![];
+	for i in 0..1000
+	{
+		if host_frames.len() < 50
+		{
+			if let Some(frame) = host.step([i as f32, 0., 0., 0., 0.]).unwrap()
+			{
+				host_frames.push(frame);
+			}
+		}
+		if client_frames.len() < 50
+		{
+			if let Some(frame) = client.step([0., i as f32, 0., 0., 0.]).unwrap()
+			{
+				client_frames.push(frame);
+			}
+		}
+		if host_frames.len() == 50 && client_frames.len() == 50
+		{
+			break;
+		}
+		std::thread::sleep(std::time::Duration::from_millis(1));
+	}
+	assert_eq!(host_frames.len(), 50);
+	assert_eq!(host_frames, client_frames);
+}
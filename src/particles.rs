@@ -0,0 +1,125 @@
+use crate::error::Result;
+use crate::utils;
+use allegro::*;
+use na::Vector3;
+use nalgebra as na;
+use rand::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+fn default_color() -> Vec<[f32; 4]>
+{
+	vec![[1., 1., 1., 1.]]
+}
+
+fn default_scale() -> Vec<f32>
+{
+	vec![1.]
+}
+
+/// Samples `keys`, spread evenly over a particle's life, at `f` (0 to 1) of the
+/// way through it.
+fn sample_keys<const N: usize>(keys: &[[f32; N]], f: f32) -> [f32; N]
+{
+	if keys.is_empty()
+	{
+		return [1.; N];
+	}
+	let t = utils::clamp(f, 0., 1.) * (keys.len() - 1) as f32;
+	let i = utils::min(t as usize, keys.len() - 1);
+	let j = utils::min(i + 1, keys.len() - 1);
+	let frac = t - i as f32;
+	let mut ret = [0.; N];
+	for k in 0..N
+	{
+		ret[k] = keys[i][k] + (keys[j][k] - keys[i][k]) * frac;
+	}
+	ret
+}
+
+/// Describes the particles an emitter spawns, loaded from a config file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Emitter
+{
+	// Particles without a sprite are invisible, but can still have a trail.
+	#[serde(default)]
+	pub sprite: Option<String>,
+	// Particles per second.
+	#[serde(default)]
+	pub rate: f32,
+	// Particles spawned at once when the emitter starts.
+	#[serde(default)]
+	pub burst: i32,
+	pub lifetime: f64,
+	#[serde(default)]
+	pub vel_min: [f32; 3],
+	#[serde(default)]
+	pub vel_max: [f32; 3],
+	// Downwards acceleration.
+	#[serde(default)]
+	pub gravity: f32,
+	// Fraction of the velocity lost per second.
+	#[serde(default)]
+	pub drag: f32,
+	// Keys spread evenly over the particle's life.
+	#[serde(default = "default_color")]
+	pub color: Vec<[f32; 4]>,
+	#[serde(default = "default_scale")]
+	pub scale: Vec<f32>,
+	// Another emitter attached to each particle.
+	#[serde(default)]
+	pub trail: Option<String>,
+	// Used instead once the emitting entity is badly damaged.
+	#[serde(default)]
+	pub smoke: Option<String>,
+}
+
+impl Emitter
+{
+	pub fn load(file: &str) -> Result<Emitter>
+	{
+		utils::load_config(file)
+	}
+
+	pub fn get_vel<R: Rng>(&self, rng: &mut R) -> Vector3<f32>
+	{
+		let mut vel = Vector3::zeros();
+		for i in 0..3
+		{
+			let (min, max) = (self.vel_min[i], self.vel_max[i]);
+			vel[i] = if max > min
+			{
+				rng.gen_range(min..max)
+			}
+			else
+			{
+				min
+			};
+		}
+		vel
+	}
+
+	/// The (premultiplied) tint `f` (0 to 1) of the way through a particle's
+	/// life.
+	pub fn get_color(&self, f: f32) -> Color
+	{
+		let [r, g, b, a] = sample_keys(&self.color, f);
+		Color::from_rgba_f(r * a, g * a, b * a, a)
+	}
+
+	pub fn get_scale(&self, f: f32) -> f32
+	{
+		let keys: Vec<_> = self.scale.iter().map(|&s| [s]).collect();
+		sample_keys(&keys, f)[0]
+	}
+}
+
+#[test]
+fn test_sample_keys()
+{
+	let keys = [[0., 1.], [1., 0.], [3., 0.]];
+	assert_eq!(sample_keys(&keys, 0.), [0., 1.]);
+	assert_eq!(sample_keys(&keys, 0.25), [0.5, 0.5]);
+	assert_eq!(sample_keys(&keys, 0.75), [2., 0.]);
+	assert_eq!(sample_keys(&keys, 2.), [3., 0.]);
+	assert_eq!(sample_keys(&[[2.]], 0.5), [2.]);
+}
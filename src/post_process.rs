@@ -0,0 +1,168 @@
+use crate::error::{Error, Result};
+use crate::utils;
+
+use allegro::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PassDesc
+{
+	pixel_shader: String,
+	// Uniforms the pass reads, with the values used when game code doesn't set
+	// them.
+	#[serde(default)]
+	uniforms: HashMap<String, f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChainDesc
+{
+	vertex_shader: String,
+	passes: Vec<PassDesc>,
+}
+
+struct Pass
+{
+	shader: sync::Weak<Shader>,
+	uniforms: HashMap<String, f32>,
+}
+
+/// An ordered chain of full-screen pixel shaders, described by a config file.
+pub struct PostProcess
+{
+	file: String,
+	passes: Vec<Pass>,
+	buffers: [Bitmap; 2],
+}
+
+fn build_shader(
+	display: &mut Display, vertex_file: &str, pixel_file: &str,
+) -> Result<sync::Weak<Shader>>
+{
+	let shader = display
+		.create_shader(ShaderPlatform::GLSL)
+		.map_err(|_| "Couldn't create shader".to_string())?;
+	let strong = shader
+		.upgrade()
+		.ok_or_else(|| "Shader was destroyed".to_string())?;
+	strong
+		.attach_shader_source(
+			ShaderType::Vertex,
+			Some(&utils::read_to_string(vertex_file)?),
+		)
+		.map_err(|e| Error::new(format!("Couldn't compile '{}':\n{}", vertex_file, e), None))?;
+	strong
+		.attach_shader_source(ShaderType::Pixel, Some(&utils::read_to_string(pixel_file)?))
+		.map_err(|e| Error::new(format!("Couldn't compile '{}':\n{}", pixel_file, e), None))?;
+	strong.build().map_err(|e| {
+		Error::new(
+			format!(
+				"Couldn't link '{}' with '{}':\n{}",
+				vertex_file, pixel_file, e
+			),
+			None,
+		)
+	})?;
+	Ok(shader)
+}
+
+fn load_passes(file: &str, display: &mut Display) -> Result<Vec<Pass>>
+{
+	let desc: ChainDesc = utils::load_config(file)?;
+	let mut passes = vec![];
+	for pass in desc.passes
+	{
+		passes.push(Pass {
+			shader: build_shader(display, &desc.vertex_shader, &pass.pixel_shader)?,
+			uniforms: pass.uniforms,
+		});
+	}
+	Ok(passes)
+}
+
+impl PostProcess
+{
+	pub fn new(
+		file: &str, display: &mut Display, core: &Core, width: i32, height: i32,
+	) -> Result<Self>
+	{
+		let passes = load_passes(file, display)
+			.map_err(|e| e.context(format!("Couldn't load '{}'", file)))?;
+		let make_buffer = || {
+			Bitmap::new(core, width, height)
+				.map_err(|_| "Couldn't create post-processing buffer".to_string())
+		};
+		Ok(Self {
+			file: file.to_string(),
+			passes,
+			buffers: [make_buffer()?, make_buffer()?],
+		})
+	}
+
+	pub fn resize(&mut self, core: &Core, width: i32, height: i32) -> Result<()>
+	{
+		for buffer in &mut self.buffers
+		{
+			*buffer = Bitmap::new(core, width, height)
+				.map_err(|_| "Couldn't create post-processing buffer".to_string())?;
+		}
+		Ok(())
+	}
+
+	/// Rebuilds the chain from its config file. If that fails, the old chain is
+	/// kept. The old shaders stay alive until the display is destroyed.
+	pub fn reload(&mut self, display: &mut Display) -> Result<()>
+	{
+		self.passes = load_passes(&self.file, display)
+			.map_err(|e| e.context(format!("Couldn't reload '{}'", self.file)))?;
+		Ok(())
+	}
+
+	/// Runs `source` through every pass, and returns the bitmap holding the
+	/// result. `uniforms` override the defaults from the config file.
+	pub fn apply<'l>(
+		&'l self, source: &'l Bitmap, uniforms: &HashMap<String, f32>, core: &Core,
+	) -> &'l Bitmap
+	{
+		let mut cur = source;
+		// Skipped passes don't count, or a pass could draw into its own source.
+		let mut num_applied = 0;
+		for pass in &self.passes
+		{
+			let shader = match pass.shader.upgrade()
+			{
+				Some(shader) => shader,
+				None => continue,
+			};
+			let target = &self.buffers[num_applied % 2];
+			num_applied += 1;
+			core.set_target_bitmap(Some(target));
+			core.use_shader(Some(&*shader)).ok();
+			core.set_shader_uniform(
+				"bitmap_dims",
+				&[[cur.get_width() as f32, cur.get_height() as f32]][..],
+			)
+			.ok();
+			for (name, &default) in &pass.uniforms
+			{
+				let value = uniforms.get(name).cloned().unwrap_or(default);
+				core.set_shader_uniform(name, &[value][..]).ok();
+			}
+			core.draw_bitmap(cur, 0., 0., Flag::zero());
+			cur = target;
+		}
+		core.use_shader(None).ok();
+		cur
+	}
+}
+
+#[test]
+fn test_load_chain()
+{
+	let desc: ChainDesc = utils::load_config("data/post_process.cfg").unwrap();
+	assert_eq!(desc.passes.len(), 2);
+	assert_eq!(desc.passes[0].uniforms.get("swirl_amount"), Some(&0.));
+	assert_eq!(desc.passes[1].uniforms.get("damage"), Some(&0.));
+}
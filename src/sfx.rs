@@ -30,16 +30,16 @@ impl Sfx
 {
 	pub fn new(sfx_volume: f32, music_volume: f32, core: &Core) -> Result<Sfx>
 	{
-		let audio = AudioAddon::init(&core)?;
+		let audio = AudioAddon::init(core)?;
 		let acodec = AcodecAddon::init(&audio)?;
 		let sink = Sink::new(&audio).map_err(|_| "Couldn't create audio sink".to_string())?;
 
 		let mut sfx = Sfx {
 			sfx_volume: 0.,
 			music_volume: 0.,
-			audio: audio,
-			acodec: acodec,
-			sink: sink,
+			audio,
+			acodec,
+			sink,
 			sample_instances: vec![],
 			stream: None,
 			exclusive_instance: None,
@@ -67,6 +67,17 @@ impl Sfx
 		})
 	}
 
+	/// Reloads a sample if it has been cached.
+	pub fn reload_sample(&mut self, name: &str) -> Result<()>
+	{
+		if self.samples.contains_key(name)
+		{
+			self.samples
+				.insert(name.to_string(), utils::load_sample(&self.audio, name)?);
+		}
+		Ok(())
+	}
+
 	pub fn get_sample<'l>(&'l self, name: &str) -> Option<&'l Sample>
 	{
 		self.samples.get(name)
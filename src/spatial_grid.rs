@@ -0,0 +1,148 @@
+use crate::utils;
+
+use nalgebra::Point2;
+use std::collections::HashMap;
+
+type Cell<T> = Vec<(Point2<f32>, T)>;
+
+/// A spatial hash over 2D positions. Entries are bucketed into square cells,
+/// so queries only look at the cells that overlap the query shape.
+pub struct SpatialGrid<T>
+{
+	cell_size: f32,
+	cells: HashMap<(i32, i32), Cell<T>>,
+}
+
+impl<T: Clone> SpatialGrid<T>
+{
+	pub fn new(cell_size: f32) -> Self
+	{
+		Self {
+			cell_size,
+			cells: HashMap::new(),
+		}
+	}
+
+	pub fn clear(&mut self)
+	{
+		// Keep the allocations, the grid gets rebuilt every tick.
+		for cell in self.cells.values_mut()
+		{
+			cell.clear();
+		}
+	}
+
+	pub fn push(&mut self, pos: Point2<f32>, value: T)
+	{
+		let cell = self.cell_of(pos);
+		self.cells.entry(cell).or_default().push((pos, value));
+	}
+
+	/// Returns the entries within `radius` of `center`.
+	pub fn query_radius(&self, center: Point2<f32>, radius: f32) -> Vec<(Point2<f32>, T)>
+	{
+		let mut ret = vec![];
+		let min = self.cell_of(center - utils::Vec2D::new(radius, radius));
+		let max = self.cell_of(center + utils::Vec2D::new(radius, radius));
+		for x in min.0..=max.0
+		{
+			for y in min.1..=max.1
+			{
+				if let Some(cell) = self.cells.get(&(x, y))
+				{
+					for (pos, value) in cell
+					{
+						if (pos - center).norm_squared() <= radius * radius
+						{
+							ret.push((*pos, value.clone()));
+						}
+					}
+				}
+			}
+		}
+		ret
+	}
+
+	/// Returns the entry nearest to `center` that passes `filter`, searching out
+	/// to `max_radius`.
+	pub fn query_nearest<F: Fn(&T) -> bool>(
+		&self, center: Point2<f32>, max_radius: f32, filter: F,
+	) -> Option<(Point2<f32>, T)>
+	{
+		// Widen the search until something turns up, anything nearer than the
+		// radius is guaranteed to be inside it.
+		let mut radius = self.cell_size;
+		loop
+		{
+			let radius_used = utils::min(radius, max_radius);
+			let nearest = self
+				.query_radius(center, radius_used)
+				.into_iter()
+				.filter(|(_, value)| filter(value))
+				.min_by(|(a, _), (b, _)| {
+					let da = (a - center).norm_squared();
+					let db = (b - center).norm_squared();
+					da.partial_cmp(&db).unwrap()
+				});
+			if nearest.is_some() || radius_used >= max_radius
+			{
+				return nearest;
+			}
+			radius *= 2.;
+		}
+	}
+
+	fn cell_of(&self, pos: Point2<f32>) -> (i32, i32)
+	{
+		(
+			(pos.x / self.cell_size).floor() as i32,
+			(pos.y / self.cell_size).floor() as i32,
+		)
+	}
+}
+
+#[test]
+fn test_query_radius()
+{
+	let mut grid = SpatialGrid::new(2.);
+	grid.push(Point2::new(0., 0.), 0);
+	grid.push(Point2::new(1.5, 0.), 1);
+	grid.push(Point2::new(-3., 4.), 2);
+	grid.push(Point2::new(10., 10.), 3);
+
+	let mut found: Vec<_> = grid
+		.query_radius(Point2::new(0., 0.), 2.)
+		.into_iter()
+		.map(|(_, v)| v)
+		.collect();
+	found.sort();
+	assert_eq!(found, vec![0, 1]);
+
+	let found: Vec<_> = grid
+		.query_radius(Point2::new(0., 0.), 5.)
+		.into_iter()
+		.map(|(_, v)| v)
+		.collect();
+	assert_eq!(found.len(), 3);
+
+	grid.clear();
+	assert!(grid.query_radius(Point2::new(0., 0.), 100.).is_empty());
+}
+
+#[test]
+fn test_query_nearest()
+{
+	let mut grid = SpatialGrid::new(1.);
+	grid.push(Point2::new(1., 0.), 0);
+	grid.push(Point2::new(-5., 0.), 1);
+	grid.push(Point2::new(20., 0.), 2);
+
+	let nearest = |max_radius, filter: fn(&i32) -> bool| {
+		grid.query_nearest(Point2::new(0., 0.), max_radius, filter)
+			.map(|(_, v)| v)
+	};
+	assert_eq!(nearest(100., |_| true), Some(0));
+	assert_eq!(nearest(100., |&v| v != 0), Some(1));
+	assert_eq!(nearest(100., |&v| v == 2), Some(2));
+	assert_eq!(nearest(10., |&v| v == 2), None);
+}
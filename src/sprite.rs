@@ -16,6 +16,50 @@ struct SpriteDesc
 	center_x: i32,
 	#[serde(default)]
 	center_y: i32,
+	// Glows by itself, so it isn't darkened at night.
+	#[serde(default)]
+	emissive: bool,
+}
+
+fn load_variants(
+	desc: &SpriteDesc, old_variants: &[atlas::AtlasBitmap], core: &Core, atlas: &mut atlas::Atlas,
+) -> Result<Vec<atlas::AtlasBitmap>>
+{
+	let old_flags = core.get_new_bitmap_flags();
+	core.set_new_bitmap_flags(MEMORY_BITMAP);
+	let bitmap = utils::load_bitmap(core, &desc.bitmap)?;
+	core.set_new_bitmap_flags(old_flags);
+
+	let num_variants = bitmap.get_height() / desc.height;
+	let mut variants = Vec::with_capacity(num_variants as usize);
+	let mut to_remove = vec![];
+	for i in 0..num_variants
+	{
+		let sub_bitmap = bitmap
+			.create_sub_bitmap(0, i * desc.height, desc.width, desc.height)
+			.map_err(|_| "Couldn't create sub-bitmap?".to_string())?
+			.upgrade()
+			.unwrap();
+		let variant = match old_variants.get(i as usize)
+		{
+			Some(old) if atlas.replace(core, old, &*sub_bitmap) => old.clone(),
+			Some(old) =>
+			{
+				to_remove.push(old.clone());
+				atlas.insert(core, &*sub_bitmap)?
+			}
+			None => atlas.insert(core, &*sub_bitmap)?,
+		};
+		variants.push(variant);
+	}
+	// Only free the old entries once nothing can fail, the sprite keeps using
+	// them otherwise.
+	to_remove.extend(old_variants.iter().skip(variants.len()).cloned());
+	for old in &to_remove
+	{
+		atlas.remove(old);
+	}
+	Ok(variants)
 }
 
 #[derive(Clone, Debug)]
@@ -30,31 +74,28 @@ impl Sprite
 	pub fn load(sprite: &str, core: &Core, atlas: &mut atlas::Atlas) -> Result<Sprite>
 	{
 		let desc: SpriteDesc = utils::load_config(sprite)?;
+		let variants = load_variants(&desc, &[], core, atlas)?;
+		Ok(Sprite { desc, variants })
+	}
 
-		let old_flags = core.get_new_bitmap_flags();
-		core.set_new_bitmap_flags(MEMORY_BITMAP);
-		let bitmap = utils::load_bitmap(&core, &desc.bitmap)?;
-		core.set_new_bitmap_flags(old_flags);
+	/// Reloads the descriptor and bitmap, reusing the atlas entries where the
+	/// sizes still match.
+	pub fn reload(&mut self, sprite: &str, core: &Core, atlas: &mut atlas::Atlas) -> Result<()>
+	{
+		let desc: SpriteDesc = utils::load_config(sprite)?;
+		self.variants = load_variants(&desc, &self.variants, core, atlas)?;
+		self.desc = desc;
+		Ok(())
+	}
 
-		let num_variants = bitmap.get_height() / desc.height;
-		let mut variants = Vec::with_capacity(num_variants as usize);
-		for i in 0..num_variants
-		{
-			variants.push(
-				atlas.insert(
-					&core,
-					&*bitmap
-						.create_sub_bitmap(0, i * desc.height, desc.width, desc.height)
-						.map_err(|_| "Couldn't create sub-bitmap?".to_string())?
-						.upgrade()
-						.unwrap(),
-				)?,
-			)
-		}
-		Ok(Sprite {
-			desc: desc,
-			variants: variants,
-		})
+	pub fn get_bitmap_file(&self) -> &str
+	{
+		&self.desc.bitmap
+	}
+
+	pub fn is_emissive(&self) -> bool
+	{
+		self.desc.emissive
 	}
 
 	pub fn num_variants(&self) -> i32
@@ -62,6 +103,20 @@ impl Sprite
 		self.variants.len() as i32
 	}
 
+	/// The top-left and bottom-right corners of the sprite when drawn at `pos`.
+	pub fn get_bounds(&self, pos: Point2<f32>) -> (Point2<f32>, Point2<f32>)
+	{
+		let top_left = Point2::new(
+			pos.x - self.desc.center_x as f32,
+			pos.y - self.desc.center_y as f32,
+		);
+		let bottom_right = Point2::new(
+			top_left.x + self.desc.width as f32,
+			top_left.y + self.desc.height as f32,
+		);
+		(top_left, bottom_right)
+	}
+
 	pub fn draw(&self, pos: Point2<f32>, variant: i32, tint: Color, state: &GameState)
 	{
 		let w = self.desc.width as f32;
@@ -80,4 +135,29 @@ impl Sprite
 			Flag::zero(),
 		);
 	}
+
+	/// Like `draw`, but scaled by `scale` around the sprite's center.
+	pub fn draw_scaled(
+		&self, pos: Point2<f32>, variant: i32, tint: Color, scale: f32, state: &GameState,
+	)
+	{
+		let atlas_bmp = &self.variants[variant as usize];
+
+		state.core.draw_tinted_scaled_rotated_bitmap_region(
+			&state.atlas.pages[atlas_bmp.page].bitmap,
+			atlas_bmp.start.x,
+			atlas_bmp.start.y,
+			self.desc.width as f32,
+			self.desc.height as f32,
+			tint,
+			self.desc.center_x as f32,
+			self.desc.center_y as f32,
+			pos.x,
+			pos.y,
+			scale,
+			scale,
+			0.,
+			Flag::zero(),
+		);
+	}
 }
@@ -6,7 +6,7 @@ use allegro_font::*;
 use allegro_sys::*;
 use nalgebra::{Matrix4, Point2, Vector2, Vector3};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum Action
 {
 	SelectMe,
@@ -25,7 +25,25 @@ pub enum Action
 	FireStartProbability(f32),
 	ObeliskFactor(f32),
 	WaterFactor(f32),
+	NumWingmen(f32),
+	NightMission(bool),
+	NumPlayers(f32),
+	HostAddress(String),
+	Host,
+	Join,
 	MouseSensitivity(f32),
+	DeadZone(f32),
+	ControlsProfile(usize),
+	ToggleMouseFlight,
+	ToggleInvertY,
+	ControlsPage(usize),
+	GameSpeed(f32),
+	ToggleAutoLevel,
+	ToggleAutoDropWater,
+	ToggleHoldMode(controls::Action),
+	OptionsPage(usize),
+	Resolution(usize),
+	ToggleIntegerScaling,
 }
 
 #[derive(Clone)]
@@ -46,7 +64,7 @@ impl Button
 			loc: Point2::new(x, y),
 			size: Vector2::new(w, h),
 			text: text.into(),
-			action: action,
+			action,
 			selected: false,
 		}
 	}
@@ -96,26 +114,6 @@ impl Button
 					return Some(Action::SelectMe);
 				}
 			}
-			Event::KeyDown { keycode, .. } => match keycode
-			{
-				KeyCode::Enter | KeyCode::Space =>
-				{
-					if self.selected
-					{
-						state.sfx.play_sound("data/ui2.ogg").unwrap();
-						return Some(self.action.clone());
-					}
-				}
-				KeyCode::Escape =>
-				{
-					if self.action == Action::Back
-					{
-						state.sfx.play_sound("data/ui2.ogg").unwrap();
-						return Some(self.action.clone());
-					}
-				}
-				_ => (),
-			},
 			Event::MouseButtonUp { x, y, .. } =>
 			{
 				let (x, y) = state.transform_mouse(*x as f32, *y as f32);
@@ -127,10 +125,20 @@ impl Button
 			}
 			_ => (),
 		}
+		let event_actions = state.controls.get_event_actions();
+		if (self.selected && event_actions.contains(&controls::Action::MenuConfirm))
+			|| (matches!(self.action, Action::Back)
+				&& event_actions.contains(&controls::Action::MenuBack))
+		{
+			state.sfx.play_sound("data/ui2.ogg").unwrap();
+			return Some(self.action.clone());
+		}
 		None
 	}
 }
 
+type ToggleFn = fn(usize) -> Action;
+
 #[derive(Clone)]
 struct Toggle
 {
@@ -138,23 +146,22 @@ struct Toggle
 	size: Vector2<f32>,
 	texts: Vec<String>,
 	cur_value: usize,
-	action_fn: fn(usize) -> Action,
+	action_fn: ToggleFn,
 	selected: bool,
 }
 
 impl Toggle
 {
 	fn new(
-		x: f32, y: f32, w: f32, h: f32, cur_value: usize, texts: Vec<String>,
-		action_fn: fn(usize) -> Action,
+		x: f32, y: f32, w: f32, h: f32, cur_value: usize, texts: Vec<String>, action_fn: ToggleFn,
 	) -> Self
 	{
 		Self {
 			loc: Point2::new(x, y),
 			size: Vector2::new(w, h),
-			texts: texts,
-			cur_value: cur_value,
-			action_fn: action_fn,
+			texts,
+			cur_value,
+			action_fn,
 			selected: false,
 		}
 	}
@@ -204,17 +211,6 @@ impl Toggle
 					return Some(Action::SelectMe);
 				}
 			}
-			Event::KeyUp { keycode, .. } => match keycode
-			{
-				KeyCode::Enter | KeyCode::Space =>
-				{
-					if self.selected
-					{
-						return Some(self.trigger(state));
-					}
-				}
-				_ => (),
-			},
 			Event::MouseButtonUp { x, y, .. } =>
 			{
 				let (x, y) = state.transform_mouse(*x as f32, *y as f32);
@@ -225,6 +221,14 @@ impl Toggle
 			}
 			_ => (),
 		}
+		if self.selected
+			&& state
+				.controls
+				.get_event_actions()
+				.contains(&controls::Action::MenuConfirm)
+		{
+			return Some(self.trigger(state));
+		}
 		None
 	}
 
@@ -253,6 +257,7 @@ struct Slider
 
 impl Slider
 {
+	#[allow(clippy::too_many_arguments)]
 	fn new(
 		x: f32, y: f32, w: f32, h: f32, cur_pos: f32, min_pos: f32, max_pos: f32,
 		round_to_integer: bool, action_fn: fn(f32) -> Action,
@@ -261,13 +266,13 @@ impl Slider
 		Self {
 			loc: Point2::new(x, y),
 			size: Vector2::new(w, h),
-			cur_pos: cur_pos,
-			min_pos: min_pos,
-			max_pos: max_pos,
+			cur_pos,
+			min_pos,
+			max_pos,
 			grabbed: false,
 			selected: false,
-			round_to_integer: round_to_integer,
-			action_fn: action_fn,
+			round_to_integer,
+			action_fn,
 		}
 	}
 
@@ -319,7 +324,7 @@ impl Slider
 		}
 		else
 		{
-			format!("{:.1}", self.cur_pos)
+			format!("{:.2}", self.cur_pos)
 		};
 
 		state.core.draw_text(
@@ -371,43 +376,35 @@ impl Slider
 					return Some((self.action_fn)(self.cur_pos));
 				}
 			}
-			Event::KeyDown { keycode, .. } =>
+			_ => (),
+		}
+		if self.selected
+		{
+			let increment = if self.round_to_integer
+			{
+				1.
+			}
+			else
 			{
-				let increment = if self.round_to_integer
-				{
-					1.
-				}
-				else
-				{
-					(self.max_pos - self.min_pos) / 25.
-				};
-				if self.selected
+				(self.max_pos - self.min_pos) / 25.
+			};
+			let event_actions = state.controls.get_event_actions();
+			if event_actions.contains(&controls::Action::MenuLeft)
+			{
+				if self.cur_pos > self.min_pos
 				{
-					match keycode
-					{
-						KeyCode::Left =>
-						{
-							if self.cur_pos > self.min_pos
-							{
-								state.sfx.play_sound("data/ui2.ogg").unwrap();
-								self.cur_pos = utils::max(self.min_pos, self.cur_pos - increment);
-								return Some((self.action_fn)(self.cur_pos));
-							}
-						}
-						KeyCode::Right =>
-						{
-							if self.cur_pos < self.max_pos
-							{
-								state.sfx.play_sound("data/ui2.ogg").unwrap();
-								self.cur_pos = utils::min(self.max_pos, self.cur_pos + increment);
-								return Some((self.action_fn)(self.cur_pos));
-							}
-						}
-						_ => (),
-					}
+					state.sfx.play_sound("data/ui2.ogg").unwrap();
+					self.cur_pos = utils::max(self.min_pos, self.cur_pos - increment);
+					return Some((self.action_fn)(self.cur_pos));
 				}
 			}
-			_ => (),
+			else if event_actions.contains(&controls::Action::MenuRight)
+				&& self.cur_pos < self.max_pos
+			{
+				state.sfx.play_sound("data/ui2.ogg").unwrap();
+				self.cur_pos = utils::min(self.max_pos, self.cur_pos + increment);
+				return Some((self.action_fn)(self.cur_pos));
+			}
 		}
 		None
 	}
@@ -460,6 +457,112 @@ impl Label
 	}
 }
 
+#[derive(Clone)]
+struct TextEntry
+{
+	loc: Point2<f32>,
+	size: Vector2<f32>,
+	text: String,
+	max_len: usize,
+	action_fn: fn(String) -> Action,
+	selected: bool,
+}
+
+impl TextEntry
+{
+	fn new(
+		x: f32, y: f32, w: f32, h: f32, text: &str, max_len: usize, action_fn: fn(String) -> Action,
+	) -> Self
+	{
+		Self {
+			loc: Point2::new(x, y),
+			size: Vector2::new(w, h),
+			text: text.into(),
+			max_len,
+			action_fn,
+			selected: false,
+		}
+	}
+
+	fn width(&self) -> f32
+	{
+		self.size.x
+	}
+
+	fn height(&self) -> f32
+	{
+		self.size.y
+	}
+
+	fn draw(&self, state: &game_state::GameState)
+	{
+		let c_ui = if self.selected
+		{
+			Color::from_rgb_f(1., 1., 1.)
+		}
+		else
+		{
+			Color::from_rgb_f(0.8, 0.6, 0.4)
+		};
+
+		let cursor = if self.selected && (state.core.get_time() * 2.) as i32 % 2 == 0
+		{
+			"_"
+		}
+		else
+		{
+			" "
+		};
+
+		state.core.draw_text(
+			&state.ui_font,
+			c_ui,
+			self.loc.x,
+			self.loc.y - state.ui_font.get_line_height() as f32 / 2.,
+			FontAlign::Centre,
+			&format!("{}{}", self.text, cursor),
+		);
+	}
+
+	fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
+	{
+		let start = self.loc - self.size / 2.;
+		let end = self.loc + self.size / 2.;
+		match event
+		{
+			Event::MouseAxes { x, y, .. } =>
+			{
+				let (x, y) = state.transform_mouse(*x as f32, *y as f32);
+				if x > start.x && x < end.x && y > start.y && y < end.y
+				{
+					return Some(Action::SelectMe);
+				}
+			}
+			Event::KeyChar {
+				keycode, unichar, ..
+			} if self.selected =>
+			{
+				if *keycode == KeyCode::Backspace
+				{
+					if self.text.pop().is_some()
+					{
+						state.sfx.play_sound("data/ui1.ogg").unwrap();
+						return Some((self.action_fn)(self.text.clone()));
+					}
+				}
+				else if unichar.is_ascii_graphic() && self.text.len() < self.max_len
+				{
+					state.sfx.play_sound("data/ui1.ogg").unwrap();
+					self.text.push(*unichar);
+					return Some((self.action_fn)(self.text.clone()));
+				}
+			}
+			_ => (),
+		}
+		None
+	}
+}
+
 #[derive(Clone)]
 enum Widget
 {
@@ -467,6 +570,7 @@ enum Widget
 	Label(Label),
 	Slider(Slider),
 	Toggle(Toggle),
+	TextEntry(TextEntry),
 }
 
 impl Widget
@@ -479,6 +583,7 @@ impl Widget
 			Widget::Label(w) => w.height(),
 			Widget::Slider(w) => w.height(),
 			Widget::Toggle(w) => w.height(),
+			Widget::TextEntry(w) => w.height(),
 		}
 	}
 
@@ -490,6 +595,7 @@ impl Widget
 			Widget::Label(w) => w.width(),
 			Widget::Slider(w) => w.width(),
 			Widget::Toggle(w) => w.width(),
+			Widget::TextEntry(w) => w.width(),
 		}
 	}
 
@@ -501,6 +607,7 @@ impl Widget
 			Widget::Label(w) => w.loc,
 			Widget::Slider(w) => w.loc,
 			Widget::Toggle(w) => w.loc,
+			Widget::TextEntry(w) => w.loc,
 		}
 	}
 
@@ -512,6 +619,7 @@ impl Widget
 			Widget::Label(_) => false,
 			Widget::Slider(_) => true,
 			Widget::Toggle(_) => true,
+			Widget::TextEntry(_) => true,
 		}
 	}
 
@@ -523,6 +631,7 @@ impl Widget
 			Widget::Label(ref mut w) => w.loc = loc,
 			Widget::Slider(ref mut w) => w.loc = loc,
 			Widget::Toggle(ref mut w) => w.loc = loc,
+			Widget::TextEntry(ref mut w) => w.loc = loc,
 		}
 	}
 
@@ -534,6 +643,7 @@ impl Widget
 			Widget::Label(_) => false,
 			Widget::Slider(w) => w.selected,
 			Widget::Toggle(w) => w.selected,
+			Widget::TextEntry(w) => w.selected,
 		}
 	}
 
@@ -545,6 +655,7 @@ impl Widget
 			Widget::Label(_) => (),
 			Widget::Slider(ref mut w) => w.selected = selected,
 			Widget::Toggle(ref mut w) => w.selected = selected,
+			Widget::TextEntry(ref mut w) => w.selected = selected,
 		}
 	}
 
@@ -556,6 +667,7 @@ impl Widget
 			Widget::Label(w) => w.draw(state),
 			Widget::Slider(w) => w.draw(state),
 			Widget::Toggle(w) => w.draw(state),
+			Widget::TextEntry(w) => w.draw(state),
 		}
 	}
 
@@ -567,6 +679,7 @@ impl Widget
 			Widget::Label(w) => w.input(state, event),
 			Widget::Slider(w) => w.input(state, event),
 			Widget::Toggle(w) => w.input(state, event),
+			Widget::TextEntry(w) => w.input(state, event),
 		}
 	}
 }
@@ -656,6 +769,19 @@ impl WidgetList
 		}
 	}
 
+	fn set_selection(&mut self, selection: (usize, usize))
+	{
+		let (i, j) = selection;
+		if i >= self.widgets.len() || j >= self.widgets[i].len() || !self.widgets[i][j].selectable()
+		{
+			return;
+		}
+		let (old_i, old_j) = self.cur_selection;
+		self.widgets[old_i][old_j].set_selected(false);
+		self.widgets[i][j].set_selected(true);
+		self.cur_selection = selection;
+	}
+
 	pub fn draw(&self, state: &game_state::GameState)
 	{
 		for row in &self.widgets
@@ -688,13 +814,14 @@ impl WidgetList
 				}
 			}
 		}
-		if action.is_none() || action == Some(Action::SelectMe)
+		if matches!(action, None | Some(Action::SelectMe))
 		{
-			match event
+			let event_actions = state.controls.get_event_actions().to_vec();
+			for event_action in event_actions
 			{
-				Event::KeyDown { keycode, .. } => match *keycode
+				match event_action
 				{
-					KeyCode::Up =>
+					controls::Action::MenuUp =>
 					{
 						state.sfx.play_sound("data/ui1.ogg").unwrap();
 						'found1: loop
@@ -718,7 +845,7 @@ impl WidgetList
 							}
 						}
 					}
-					KeyCode::Down =>
+					controls::Action::MenuDown =>
 					{
 						state.sfx.play_sound("data/ui1.ogg").unwrap();
 						'found2: loop
@@ -742,7 +869,7 @@ impl WidgetList
 							}
 						}
 					}
-					KeyCode::Left =>
+					controls::Action::MenuLeft =>
 					{
 						state.sfx.play_sound("data/ui1.ogg").unwrap();
 						let row_len = self.widgets[self.cur_selection.0].len();
@@ -755,7 +882,7 @@ impl WidgetList
 							}
 						}
 					}
-					KeyCode::Right =>
+					controls::Action::MenuRight =>
 					{
 						state.sfx.play_sound("data/ui1.ogg").unwrap();
 						let row_len = self.widgets[self.cur_selection.0].len();
@@ -769,8 +896,7 @@ impl WidgetList
 						}
 					}
 					_ => (),
-				},
-				_ => (),
+				}
 			}
 		}
 		self.widgets[old_selection.0][old_selection.1].set_selected(false);
@@ -808,6 +934,16 @@ impl MainMenu
 						"NEW GAME",
 						Action::LevelMenu,
 					))],
+					&[Widget::Button(Button::new(
+						0.,
+						0.,
+						w,
+						h,
+						"NETWORK GAME",
+						Action::Forward(|s, dx, dy| {
+							SubScreen::NetworkMenu(NetworkMenu::new(s, dx, dy))
+						}),
+					))],
 					&[Widget::Button(Button::new(
 						0.,
 						0.,
@@ -815,7 +951,17 @@ impl MainMenu
 						h,
 						"CONTROLS",
 						Action::Forward(|s, dx, dy| {
-							SubScreen::ControlsMenu(ControlsMenu::new(s, dx, dy))
+							SubScreen::ControlsMenu(ControlsMenu::new(s, dx, dy, 0))
+						}),
+					))],
+					&[Widget::Button(Button::new(
+						0.,
+						0.,
+						w,
+						h,
+						"P2 CONTROLS",
+						Action::Forward(|s, dx, dy| {
+							SubScreen::ControlsMenu(ControlsMenu::new(s, dx, dy, 1))
 						}),
 					))],
 					&[Widget::Button(Button::new(
@@ -856,58 +1002,84 @@ pub struct ControlsMenu
 {
 	widgets: WidgetList,
 	accepting_input: bool,
+	player: usize,
+	display_width: f32,
+	display_height: f32,
+	page: usize,
 }
 
 impl ControlsMenu
 {
-	pub fn new(state: &game_state::GameState, display_width: f32, display_height: f32) -> Self
+	pub fn new(
+		state: &game_state::GameState, display_width: f32, display_height: f32, player: usize,
+	) -> Self
+	{
+		Self::with_page(state, display_width, display_height, player, 0)
+	}
+
+	fn with_page(
+		state: &game_state::GameState, display_width: f32, display_height: f32, player: usize,
+		page: usize,
+	) -> Self
 	{
 		let w = 256.;
+		let bw = 160.;
 		let h = 20.;
 		let cx = display_width / 2.;
 		let cy = display_height / 2.;
 
 		let mut widgets = vec![];
-		widgets.push(vec![
-			Widget::Label(Label::new(0., 0., w * 1.5, h, "MOUSE SENSITIVITY")),
-			Widget::Slider(Slider::new(
+		if player > 0
+		{
+			widgets.push(vec![Widget::Label(Label::new(
 				0.,
 				0.,
 				w,
 				h,
-				state.controls.get_mouse_sensitivity(),
+				&format!("PLAYER {}", player + 1),
+			))]);
+		}
+		widgets.push(vec![
+			Widget::Label(Label::new(0., 0., w, h, "PROFILE")),
+			Widget::Toggle(Toggle::new(
 				0.,
-				2.,
-				false,
-				|i| Action::MouseSensitivity(i),
+				0.,
+				w,
+				h,
+				state.get_player_profile(player),
+				state
+					.options
+					.control_profiles
+					.iter()
+					.map(|p| p.get_name().to_uppercase())
+					.collect(),
+				Action::ControlsProfile,
 			)),
 		]);
-
-		for (&action, &inputs) in state.controls.get_actions_to_inputs()
-		{
-			let mut row = vec![Widget::Label(Label::new(
+		widgets.push(vec![
+			Widget::Label(Label::new(0., 0., w, h, "SHOW")),
+			Widget::Toggle(Toggle::new(
 				0.,
 				0.,
 				w,
 				h,
-				&action.to_str().to_uppercase(),
-			))];
-			for i in 0..2
-			{
-				let input = inputs[i];
-				let input_str = input
-					.map(|i| i.to_str().to_uppercase())
-					.unwrap_or("NONE".into());
-				row.push(Widget::Button(Button::new(
-					0.,
-					0.,
-					w,
-					h,
-					&input_str,
-					Action::ChangeInput(action, i),
-				)));
-			}
-			widgets.push(row);
+				page,
+				vec![
+					"FLIGHT".into(),
+					"CAMERA".into(),
+					"MENU".into(),
+					"DEVICES".into(),
+				],
+				Action::ControlsPage,
+			)),
+		]);
+		if page == 3
+		{
+			ControlsMenu::add_device_rows(state, &mut widgets, player, w, h);
+		}
+		else
+		{
+			ControlsMenu::add_binding_rows(state, &mut widgets, player, page, w, bw, h);
 		}
 		widgets.push(vec![Widget::Button(Button::new(
 			0.,
@@ -927,58 +1099,184 @@ impl ControlsMenu
 				&widgets.iter().map(|r| &r[..]).collect::<Vec<_>>(),
 			),
 			accepting_input: false,
+			player,
+			display_width,
+			display_height,
+			page,
 		}
 	}
 
-	pub fn draw(&self, state: &game_state::GameState)
+	fn add_device_rows(
+		state: &game_state::GameState, widgets: &mut Vec<Vec<Widget>>, player: usize, w: f32,
+		h: f32,
+	)
 	{
-		self.widgets.draw(state);
+		widgets.push(vec![
+			Widget::Label(Label::new(0., 0., w * 1.5, h, "MOUSE SENSITIVITY")),
+			Widget::Slider(Slider::new(
+				0.,
+				0.,
+				w,
+				h,
+				state.get_player_controls(player).get_mouse_sensitivity(),
+				0.,
+				2.,
+				false,
+				Action::MouseSensitivity,
+			)),
+		]);
+		widgets.push(vec![
+			Widget::Label(Label::new(0., 0., w, h, "MOUSE FLIGHT")),
+			Widget::Toggle(Toggle::new(
+				0.,
+				0.,
+				w / 2.,
+				h,
+				state.get_player_controls(player).get_mouse_flight() as usize,
+				vec!["NO".into(), "YES".into()],
+				|_| Action::ToggleMouseFlight,
+			)),
+		]);
+		widgets.push(vec![
+			Widget::Label(Label::new(0., 0., w, h, "INVERT Y")),
+			Widget::Toggle(Toggle::new(
+				0.,
+				0.,
+				w / 2.,
+				h,
+				state.get_player_controls(player).get_invert_y() as usize,
+				vec!["NO".into(), "YES".into()],
+				|_| Action::ToggleInvertY,
+			)),
+		]);
+		widgets.push(vec![
+			Widget::Label(Label::new(0., 0., w * 1.5, h, "STICK DEAD ZONE")),
+			Widget::Slider(Slider::new(
+				0.,
+				0.,
+				w,
+				h,
+				state.get_player_controls(player).get_dead_zone(),
+				0.,
+				0.9,
+				false,
+				Action::DeadZone,
+			)),
+		]);
 	}
 
-	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
+	fn add_binding_rows(
+		state: &game_state::GameState, widgets: &mut Vec<Vec<Widget>>, player: usize, page: usize,
+		w: f32, bw: f32, h: f32,
+	)
 	{
-		let mut action = None;
-		let mut options_changed = false;
-		if self.accepting_input
+		for (&action, bindings) in state.get_player_controls(player).get_bindings()
 		{
-			match &mut self.widgets.widgets[self.widgets.cur_selection.0]
-				[self.widgets.cur_selection.1]
+			let action_page = if action.is_menu()
 			{
-				Widget::Button(b) =>
-				{
-					if let Action::ChangeInput(action, index) = b.action
-					{
-						if let Some(changed) = state.controls.change_action(action, index, event)
-						{
-							options_changed = changed;
-							state.sfx.play_sound("data/ui2.ogg").unwrap();
-							self.accepting_input = false;
-						}
-					}
-				}
-				_ => (),
+				2
 			}
-		}
-		else
-		{
-			if let allegro::Event::KeyDown {
-				keycode: allegro::KeyCode::Delete,
-				..
-			} = event
+			else if action.is_camera()
 			{
-				match &mut self.widgets.widgets[self.widgets.cur_selection.0]
-					[self.widgets.cur_selection.1]
-				{
-					Widget::Button(b) =>
-					{
-						if let Action::ChangeInput(action, index) = b.action
-						{
-							state.controls.clear_action(action, index);
-							options_changed = true;
-							state.sfx.play_sound("data/ui2.ogg").unwrap();
-						}
+				1
+			}
+			else
+			{
+				0
+			};
+			if action_page != page
+			{
+				continue;
+			}
+			let mut row = vec![Widget::Label(Label::new(
+				0.,
+				0.,
+				w,
+				h,
+				&action.to_str().to_uppercase(),
+			))];
+			for (i, binding) in bindings.iter().enumerate()
+			{
+				row.push(Widget::Button(Button::new(
+					0.,
+					0.,
+					bw,
+					h,
+					&binding.to_str().to_uppercase(),
+					Action::ChangeInput(action, i),
+				)));
+			}
+			row.push(Widget::Button(Button::new(
+				0.,
+				0.,
+				bw,
+				h,
+				"ADD",
+				Action::ChangeInput(action, bindings.len()),
+			)));
+			widgets.push(row);
+		}
+	}
+
+	fn rebuild(&mut self, state: &game_state::GameState)
+	{
+		let selection = self.widgets.cur_selection;
+		*self = ControlsMenu::with_page(
+			state,
+			self.display_width,
+			self.display_height,
+			self.player,
+			self.page,
+		);
+		self.widgets.set_selection(selection);
+	}
+
+	pub fn draw(&self, state: &game_state::GameState)
+	{
+		self.widgets.draw(state);
+	}
+
+	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
+	{
+		let mut action = None;
+		let mut options_changed = false;
+		if self.accepting_input
+		{
+			if let Widget::Button(b) = &mut self.widgets.widgets[self.widgets.cur_selection.0]
+				[self.widgets.cur_selection.1]
+			{
+				if let Action::ChangeInput(action, index) = b.action
+				{
+					if state
+						.get_player_controls_mut(self.player)
+						.change_action(action, index, event)
+						.is_some()
+					{
+						options_changed = true;
+						state.sfx.play_sound("data/ui2.ogg").unwrap();
+						self.accepting_input = false;
+					}
+				}
+			}
+		}
+		else
+		{
+			if let allegro::Event::KeyDown {
+				keycode: allegro::KeyCode::Delete,
+				..
+			} = event
+			{
+				if let Widget::Button(b) = &mut self.widgets.widgets[self.widgets.cur_selection.0]
+					[self.widgets.cur_selection.1]
+				{
+					if let Action::ChangeInput(action, index) = b.action
+					{
+						state
+							.get_player_controls_mut(self.player)
+							.clear_action(action, index);
+						options_changed = true;
+						state.sfx.play_sound("data/ui2.ogg").unwrap();
 					}
-					_ => (),
 				}
 			}
 			action = self.widgets.input(state, event);
@@ -987,16 +1285,44 @@ impl ControlsMenu
 				Some(Action::ChangeInput(_, _)) =>
 				{
 					self.accepting_input = true;
-					match &mut self.widgets.widgets[self.widgets.cur_selection.0]
-						[self.widgets.cur_selection.1]
+					if let Widget::Button(b) = &mut self.widgets.widgets
+						[self.widgets.cur_selection.0][self.widgets.cur_selection.1]
 					{
-						Widget::Button(b) => b.text = "PRESS INPUT".into(),
-						_ => (),
+						b.text = "PRESS INPUT".into();
 					}
 				}
+				Some(Action::ControlsPage(page)) =>
+				{
+					self.page = page;
+					self.rebuild(state);
+				}
+				Some(Action::ControlsProfile(profile)) =>
+				{
+					state.set_player_profile(self.player, profile);
+					options_changed = true;
+				}
 				Some(Action::MouseSensitivity(ms)) =>
 				{
-					state.controls.set_mouse_sensitivity(ms);
+					state
+						.get_player_controls_mut(self.player)
+						.set_mouse_sensitivity(ms);
+					options_changed = true;
+				}
+				Some(Action::ToggleMouseFlight) =>
+				{
+					let controls = state.get_player_controls_mut(self.player);
+					controls.set_mouse_flight(!controls.get_mouse_flight());
+					options_changed = true;
+				}
+				Some(Action::ToggleInvertY) =>
+				{
+					let controls = state.get_player_controls_mut(self.player);
+					controls.set_invert_y(!controls.get_invert_y());
+					options_changed = true;
+				}
+				Some(Action::DeadZone(dz)) =>
+				{
+					state.get_player_controls_mut(self.player).set_dead_zone(dz);
 					options_changed = true;
 				}
 				_ => (),
@@ -1004,96 +1330,220 @@ impl ControlsMenu
 		}
 		if options_changed
 		{
-			for widget_row in &mut self.widgets.widgets
+			state.save_player_controls(self.player);
+			game_state::save_options(&state.core, &state.options).unwrap();
+			// Bindings may have been added or removed, so the rows change shape.
+			if !self.accepting_input
 			{
-				for widget in widget_row
-				{
-					match widget
-					{
-						Widget::Button(b) =>
-						{
-							if let Action::ChangeInput(action, index) = b.action
-							{
-								b.text = state.controls.get_inputs(action).unwrap()[index]
-									.map(|a| a.to_str().to_uppercase())
-									.unwrap_or("NONE".into());
-							}
-						}
-						_ => (),
-					}
-				}
+				self.rebuild(state);
 			}
-			state.options.controls = state.controls.get_controls().clone();
-			game_state::save_options(&state.core, &state.options).unwrap();
 		}
 		action
 	}
 }
 
+pub const RESOLUTIONS: [(i32, i32); 5] =
+	[(800, 600), (1024, 768), (640, 360), (960, 540), (1280, 720)];
+
 pub struct OptionsMenu
 {
 	widgets: WidgetList,
+	display_width: f32,
+	display_height: f32,
+	page: usize,
 }
 
 impl OptionsMenu
 {
 	pub fn new(state: &game_state::GameState, display_width: f32, display_height: f32) -> Self
+	{
+		Self::with_page(state, display_width, display_height, 0)
+	}
+
+	fn with_page(
+		state: &game_state::GameState, display_width: f32, display_height: f32, page: usize,
+	) -> Self
 	{
 		let w = 256.;
 		let h = 20.;
 		let cx = display_width / 2.;
 		let cy = display_height / 2.;
 
-		let widgets = [
-			vec![
-				Widget::Label(Label::new(0., 0., w, h, "FULLSCREEN")),
-				Widget::Toggle(Toggle::new(
-					0.,
-					0.,
-					w,
-					h,
-					state.options.fullscreen as usize,
-					vec!["NO".into(), "YES".into()],
-					|_| Action::ToggleFullscreen,
-				)),
-			],
-			vec![
-				Widget::Label(Label::new(0., 0., w, h, "MUSIC VOLUME")),
-				Widget::Slider(Slider::new(
-					0.,
-					0.,
-					w,
-					h,
-					state.options.music_volume,
-					0.,
-					4.,
-					false,
-					|i| Action::MusicVolume(i),
-				)),
-			],
-			vec![
-				Widget::Label(Label::new(0., 0., w, h, "SFX VOLUME")),
-				Widget::Slider(Slider::new(
-					0.,
-					0.,
-					w,
-					h,
-					state.options.sfx_volume,
-					0.,
-					4.,
-					false,
-					|i| Action::SfxVolume(i),
-				)),
-			],
-			vec![Widget::Button(Button::new(
+		let mut widgets = vec![vec![
+			Widget::Label(Label::new(0., 0., w, h, "SHOW")),
+			Widget::Toggle(Toggle::new(
 				0.,
 				0.,
 				w,
 				h,
-				"BACK",
-				Action::Back,
-			))],
-		];
+				page,
+				vec!["GENERAL".into(), "ACCESSIBILITY".into()],
+				Action::OptionsPage,
+			)),
+		]];
+		if page == 0
+		{
+			widgets.extend([
+				vec![
+					Widget::Label(Label::new(0., 0., w, h, "FULLSCREEN")),
+					Widget::Toggle(Toggle::new(
+						0.,
+						0.,
+						w,
+						h,
+						state.options.fullscreen as usize,
+						vec!["NO".into(), "YES".into()],
+						|_| Action::ToggleFullscreen,
+					)),
+				],
+				vec![
+					Widget::Label(Label::new(0., 0., w, h, "MUSIC VOLUME")),
+					Widget::Slider(Slider::new(
+						0.,
+						0.,
+						w,
+						h,
+						state.options.music_volume,
+						0.,
+						4.,
+						false,
+						Action::MusicVolume,
+					)),
+				],
+				vec![
+					Widget::Label(Label::new(0., 0., w, h, "SFX VOLUME")),
+					Widget::Slider(Slider::new(
+						0.,
+						0.,
+						w,
+						h,
+						state.options.sfx_volume,
+						0.,
+						4.,
+						false,
+						Action::SfxVolume,
+					)),
+				],
+				vec![
+					Widget::Label(Label::new(0., 0., w, h, "RESOLUTION")),
+					Widget::Toggle(Toggle::new(
+						0.,
+						0.,
+						w,
+						h,
+						RESOLUTIONS
+							.iter()
+							.position(|&r| {
+								r == (state.options.buffer_width, state.options.buffer_height)
+							})
+							.unwrap_or(0),
+						RESOLUTIONS
+							.iter()
+							.map(|(w, h)| format!("{}X{}", w, h))
+							.collect(),
+						Action::Resolution,
+					)),
+				],
+				vec![
+					Widget::Label(Label::new(0., 0., w, h, "SCALING")),
+					Widget::Toggle(Toggle::new(
+						0.,
+						0.,
+						w,
+						h,
+						!state.options.integer_scaling as usize,
+						vec!["INTEGER".into(), "FRACTIONAL".into()],
+						|_| Action::ToggleIntegerScaling,
+					)),
+				],
+			]);
+		}
+		else
+		{
+			widgets.extend([
+				vec![
+					Widget::Label(Label::new(0., 0., w, h, "GAME SPEED")),
+					Widget::Slider(Slider::new(
+						0.,
+						0.,
+						w,
+						h,
+						state.options.game_speed,
+						0.25,
+						1.,
+						false,
+						Action::GameSpeed,
+					)),
+				],
+				vec![
+					Widget::Label(Label::new(0., 0., w, h, "AUTO LEVEL")),
+					Widget::Toggle(Toggle::new(
+						0.,
+						0.,
+						w,
+						h,
+						state.options.auto_level as usize,
+						vec!["NO".into(), "YES".into()],
+						|_| Action::ToggleAutoLevel,
+					)),
+				],
+				vec![
+					Widget::Label(Label::new(0., 0., w, h, "AUTO DROP WATER")),
+					Widget::Toggle(Toggle::new(
+						0.,
+						0.,
+						w,
+						h,
+						state.options.auto_drop_water as usize,
+						vec!["NO".into(), "YES".into()],
+						|_| Action::ToggleAutoDropWater,
+					)),
+				],
+			]);
+			let hold_modes: [(controls::Action, ToggleFn); 6] = [
+				(controls::Action::TurnLeft, |_| {
+					Action::ToggleHoldMode(controls::Action::TurnLeft)
+				}),
+				(controls::Action::TurnRight, |_| {
+					Action::ToggleHoldMode(controls::Action::TurnRight)
+				}),
+				(controls::Action::Ascend, |_| {
+					Action::ToggleHoldMode(controls::Action::Ascend)
+				}),
+				(controls::Action::Descend, |_| {
+					Action::ToggleHoldMode(controls::Action::Descend)
+				}),
+				(controls::Action::DropWater, |_| {
+					Action::ToggleHoldMode(controls::Action::DropWater)
+				}),
+				(controls::Action::Minimap, |_| {
+					Action::ToggleHoldMode(controls::Action::Minimap)
+				}),
+			];
+			for (action, action_fn) in hold_modes
+			{
+				widgets.push(vec![
+					Widget::Label(Label::new(0., 0., w, h, action.to_str())),
+					Widget::Toggle(Toggle::new(
+						0.,
+						0.,
+						w,
+						h,
+						state.options.toggle_actions.contains(&action) as usize,
+						vec!["HOLD".into(), "TOGGLE".into()],
+						action_fn,
+					)),
+				]);
+			}
+		}
+		widgets.push(vec![Widget::Button(Button::new(
+			0.,
+			0.,
+			w,
+			h,
+			"BACK",
+			Action::Back,
+		))]);
 
 		Self {
 			widgets: WidgetList::new(
@@ -1103,6 +1553,9 @@ impl OptionsMenu
 				h,
 				&widgets.iter().map(|r| &r[..]).collect::<Vec<_>>(),
 			),
+			display_width,
+			display_height,
+			page,
 		}
 	}
 
@@ -1119,6 +1572,29 @@ impl OptionsMenu
 		{
 			match action
 			{
+				Action::OptionsPage(page) =>
+				{
+					let selection = self.widgets.cur_selection;
+					*self = OptionsMenu::with_page(
+						state,
+						self.display_width,
+						self.display_height,
+						page,
+					);
+					self.widgets.set_selection(selection);
+				}
+				Action::Resolution(i) =>
+				{
+					let (width, height) = RESOLUTIONS[i];
+					state.options.buffer_width = width;
+					state.options.buffer_height = height;
+					options_changed = true;
+				}
+				Action::ToggleIntegerScaling =>
+				{
+					state.options.integer_scaling = !state.options.integer_scaling;
+					options_changed = true;
+				}
 				Action::ToggleFullscreen =>
 				{
 					state.options.fullscreen = !state.options.fullscreen;
@@ -1136,6 +1612,27 @@ impl OptionsMenu
 					state.sfx.set_sfx_volume(v);
 					options_changed = true;
 				}
+				Action::GameSpeed(v) =>
+				{
+					state.options.game_speed = v;
+					options_changed = true;
+				}
+				Action::ToggleAutoLevel =>
+				{
+					state.options.auto_level = !state.options.auto_level;
+					options_changed = true;
+				}
+				Action::ToggleAutoDropWater =>
+				{
+					state.options.auto_drop_water = !state.options.auto_drop_water;
+					options_changed = true;
+				}
+				Action::ToggleHoldMode(action) =>
+				{
+					let toggle = !state.options.toggle_actions.contains(&action);
+					state.set_toggle_action(action, toggle);
+					options_changed = true;
+				}
 				_ => return Some(action),
 			}
 		}
@@ -1161,7 +1658,7 @@ impl LevelMenu
 		let w = 256.;
 		let h = 20.;
 		let cx = display_width / 2.;
-		let cy = display_height / 2.;
+		let cy = display_height / 2. + 24.;
 
 		let widgets = [
 			vec![Widget::Button(Button::new(
@@ -1190,7 +1687,7 @@ impl LevelMenu
 					4.,
 					6.,
 					true,
-					|i| Action::MapSize(i),
+					Action::MapSize,
 				)),
 			],
 			vec![
@@ -1204,7 +1701,7 @@ impl LevelMenu
 					0.05,
 					1.,
 					false,
-					|i| Action::FireStartProbability(i),
+					Action::FireStartProbability,
 				)),
 			],
 			vec![
@@ -1218,7 +1715,7 @@ impl LevelMenu
 					0.,
 					1.,
 					false,
-					|i| Action::FireSpreadProbability(i),
+					Action::FireSpreadProbability,
 				)),
 			],
 			vec![
@@ -1232,7 +1729,7 @@ impl LevelMenu
 					0.,
 					2.,
 					false,
-					|i| Action::ObeliskFactor(i),
+					Action::ObeliskFactor,
 				)),
 			],
 			vec![
@@ -1246,7 +1743,47 @@ impl LevelMenu
 					0.1,
 					0.5,
 					false,
-					|i| Action::WaterFactor(i),
+					Action::WaterFactor,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "PLAYERS")),
+				Widget::Slider(Slider::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.num_players as f32,
+					1.,
+					2.,
+					true,
+					Action::NumPlayers,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "WINGMEN")),
+				Widget::Slider(Slider::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.num_wingmen as f32,
+					0.,
+					3.,
+					true,
+					Action::NumWingmen,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "MISSION")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.night_mission as usize,
+					vec!["DAY".into(), "NIGHT".into()],
+					|i| Action::NightMission(i == 1),
 				)),
 			],
 			vec![Widget::Button(Button::new(
@@ -1308,6 +1845,21 @@ impl LevelMenu
 					state.options.water_factor = v;
 					options_changed = true;
 				}
+				Action::NumWingmen(v) =>
+				{
+					state.options.num_wingmen = (v + 0.5) as i32;
+					options_changed = true;
+				}
+				Action::NightMission(v) =>
+				{
+					state.options.night_mission = v;
+					options_changed = true;
+				}
+				Action::NumPlayers(v) =>
+				{
+					state.options.num_players = (v + 0.5) as i32;
+					options_changed = true;
+				}
 				_ => return Some(action),
 			}
 		}
@@ -1319,6 +1871,96 @@ impl LevelMenu
 	}
 }
 
+pub struct NetworkMenu
+{
+	widgets: WidgetList,
+	display_width: f32,
+	display_height: f32,
+}
+
+impl NetworkMenu
+{
+	pub fn new(state: &game_state::GameState, display_width: f32, display_height: f32) -> Self
+	{
+		let w = 256.;
+		let h = 20.;
+		let cx = display_width / 2.;
+		let cy = display_height / 2.;
+
+		let widgets = [
+			vec![Widget::Label(Label::new(0., 0., w, h, "HOST ADDRESS"))],
+			vec![Widget::TextEntry(TextEntry::new(
+				0.,
+				0.,
+				w * 2.,
+				h,
+				&state.options.host_address,
+				32,
+				Action::HostAddress,
+			))],
+			vec![
+				Widget::Button(Button::new(0., 0., w, h, "JOIN", Action::Join)),
+				Widget::Button(Button::new(0., 0., w, h, "HOST", Action::Host)),
+			],
+			vec![
+				Widget::Button(Button::new(0., 0., w, h, "START", Action::Start)),
+				Widget::Button(Button::new(0., 0., w, h, "BACK", Action::Back)),
+			],
+		];
+
+		Self {
+			widgets: WidgetList::new(
+				cx,
+				cy,
+				h,
+				h,
+				&widgets.iter().map(|r| &r[..]).collect::<Vec<_>>(),
+			),
+			display_width,
+			display_height,
+		}
+	}
+
+	pub fn draw(&self, state: &game_state::GameState)
+	{
+		self.widgets.draw(state);
+
+		let status = state
+			.net
+			.as_ref()
+			.map(|n| n.status())
+			.unwrap_or("NOT CONNECTED".into());
+		state.core.draw_text(
+			&state.ui_font,
+			Color::from_rgb_f(0.6, 0.4, 0.2),
+			self.display_width / 2.,
+			self.display_height - 64.,
+			FontAlign::Centre,
+			&status,
+		);
+	}
+
+	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
+	{
+		let action = self.widgets.input(state, event);
+		if let Some(action) = action
+		{
+			match action
+			{
+				Action::HostAddress(address) =>
+				{
+					state.options.host_address = address;
+					game_state::save_options(&state.core, &state.options).unwrap();
+				}
+				// Starting only makes sense once hosting.
+				Action::Start if state.net.is_none() => (),
+				_ => return Some(action),
+			}
+		}
+		None
+	}
+}
+
 pub struct InGameMenu
 {
 	widgets: WidgetList,
@@ -1362,7 +2004,17 @@ impl InGameMenu
 						h,
 						"CONTROLS",
 						Action::Forward(|s, dx, dy| {
-							SubScreen::ControlsMenu(ControlsMenu::new(s, dx, dy))
+							SubScreen::ControlsMenu(ControlsMenu::new(s, dx, dy, 0))
+						}),
+					))],
+					&[Widget::Button(Button::new(
+						0.,
+						0.,
+						w,
+						h,
+						"P2 CONTROLS",
+						Action::Forward(|s, dx, dy| {
+							SubScreen::ControlsMenu(ControlsMenu::new(s, dx, dy, 1))
 						}),
 					))],
 					&[Widget::Button(Button::new(
@@ -1399,6 +2051,7 @@ impl InGameMenu
 	}
 }
 
+#[allow(clippy::enum_variant_names)]
 pub enum SubScreen
 {
 	MainMenu(MainMenu),
@@ -1406,6 +2059,7 @@ pub enum SubScreen
 	OptionsMenu(OptionsMenu),
 	InGameMenu(InGameMenu),
 	LevelMenu(LevelMenu),
+	NetworkMenu(NetworkMenu),
 }
 
 impl SubScreen
@@ -1419,6 +2073,7 @@ impl SubScreen
 			SubScreen::OptionsMenu(s) => s.draw(state),
 			SubScreen::InGameMenu(s) => s.draw(state),
 			SubScreen::LevelMenu(s) => s.draw(state),
+			SubScreen::NetworkMenu(s) => s.draw(state),
 		}
 	}
 
@@ -1431,6 +2086,16 @@ impl SubScreen
 			SubScreen::OptionsMenu(s) => s.input(state, event),
 			SubScreen::InGameMenu(s) => s.input(state, event),
 			SubScreen::LevelMenu(s) => s.input(state, event),
+			SubScreen::NetworkMenu(s) => s.input(state, event),
+		}
+	}
+	/// True while a menu is waiting for an input to bind.
+	pub fn is_capturing_input(&self) -> bool
+	{
+		match self
+		{
+			SubScreen::ControlsMenu(s) => s.accepting_input,
+			_ => false,
 		}
 	}
 }
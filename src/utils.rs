@@ -2,7 +2,6 @@ use crate::error::{Error, Result};
 use allegro::*;
 use allegro_audio::*;
 use allegro_color::*;
-use nalgebra;
 use rand::prelude::*;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
@@ -42,8 +41,7 @@ pub fn camera_project(x: f32, y: f32, z: f32, player_x: f32, player_z: f32) -> I
 {
 	let eye = Point3::new(x, y, z);
 	let target = Point3::new(player_x, y, player_z);
-	let view = Isometry3::look_at_rh(&eye, &target, &Vector3::y());
-	view
+	Isometry3::look_at_rh(&eye, &target, &Vector3::y())
 }
 
 pub fn random_color(seed: u64, saturation: f32, value: f32) -> Color
@@ -55,6 +53,7 @@ pub fn random_color(seed: u64, saturation: f32, value: f32) -> Color
 pub trait ColorExt
 {
 	fn interpolate(&self, other: Color, f: f32) -> Color;
+	fn modulate(&self, other: Color) -> Color;
 }
 
 impl ColorExt for Color
@@ -71,6 +70,13 @@ impl ColorExt for Color
 			a * fi + oa * f,
 		)
 	}
+
+	fn modulate(&self, other: Color) -> Color
+	{
+		let (r, g, b, a) = self.to_rgba_f();
+		let (or, og, ob, oa) = other.to_rgba_f();
+		Color::from_rgba_f(r * or, g * og, b * ob, a * oa)
+	}
 }
 
 pub fn max<T: PartialOrd>(x: T, y: T) -> T
@@ -134,15 +140,15 @@ pub fn load_config<T: DeserializeOwned + Clone>(file: &str) -> Result<T>
 	let contents = read_to_string(file)?;
 	let mut source = Source::new(path::Path::new(file), &contents);
 	let element = ConfigElement::from_source(&mut source)
-		.map_err(|e| Error::new(format!("Config parsing error"), Some(Box::new(e))))?;
+		.map_err(|e| Error::new("Config parsing error".to_string(), Some(Box::new(e))))?;
 	from_element::<T>(&element, Some(&source))
-		.map_err(|e| Error::new(format!("Config parsing error"), Some(Box::new(e))))
+		.map_err(|e| Error::new("Config parsing error".to_string(), Some(Box::new(e))))
 }
 
 pub fn save_config<T: Serialize>(file: &str, val: T) -> Result<()>
 {
 	let element = to_element(&val)
-		.map_err(|e| Error::new(format!("Config writing error"), Some(Box::new(e))))?;
+		.map_err(|e| Error::new("Config writing error".to_string(), Some(Box::new(e))))?;
 	std::fs::write(file, format!("{}", element))
 		.map_err(|e| Error::new(format!("Couldn't write '{}'", file), Some(Box::new(e))))?;
 	Ok(())
@@ -150,7 +156,7 @@ pub fn save_config<T: Serialize>(file: &str, val: T) -> Result<()>
 
 pub fn load_bitmap(core: &Core, file: &str) -> Result<Bitmap>
 {
-	Ok(Bitmap::load(&core, file).map_err(|_| format!("Couldn't load {}", file))?)
+	Ok(Bitmap::load(core, file).map_err(|_| format!("Couldn't load {}", file))?)
 }
 
 pub fn load_sample(audio: &AudioAddon, path: &str) -> Result<Sample>